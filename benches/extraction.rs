@@ -0,0 +1,104 @@
+//! Benchmarks of the extraction hot path: parsing, compression and the full `extract` command
+use std::ffi::OsStr;
+use std::io::Cursor;
+
+use clap::Parser;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use wikipedia_html_extractor::db;
+use wikipedia_html_extractor::extract::sql::ExtractSqlCommand;
+use wikipedia_html_extractor::extract::ArticleReader;
+use wikipedia_html_extractor::fixture::{gen_article, gen_fixture};
+
+/// The seed of all the generated articles, so every run measures the same input
+const SEED: u64 = 42;
+
+/// Parses the arguments of the `extract` command
+#[derive(Parser)]
+struct ExtractArgs {
+    #[clap(flatten)]
+    command: ExtractSqlCommand,
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for count in [100, 1000] {
+        let dump = gen_fixture(count, SEED).into_bytes();
+        group.throughput(Throughput::Bytes(dump.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &dump, |b, dump| {
+            b.iter_batched(
+                || Cursor::new(dump.clone()),
+                |input| {
+                    let reader = ArticleReader::from_reader(input).unwrap();
+                    reader.collect::<Result<Vec<_>, _>>().unwrap()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn compress(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    // A few articles together are about the size of a typical real article
+    let html = (0..10)
+        .map(|index| gen_article(&mut rng, index).body.html)
+        .collect::<String>();
+    let mut group = c.benchmark_group("compress");
+    group.throughput(Throughput::Bytes(html.len() as u64));
+    for level in [1, 3, 9, 19] {
+        // The compressor is reused between articles, like the `extract` workers do
+        let mut compressor = zstd::bulk::Compressor::new(level).unwrap();
+        group.bench_function(BenchmarkId::from_parameter(level), |b| {
+            b.iter(|| compressor.compress(html.as_bytes()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn extract(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let dump = dir.path().join("dump.ndjson");
+    let count = 1000;
+    std::fs::write(&dump, gen_fixture(count, SEED)).unwrap();
+    // Creating the schema is synced, so an empty database is copied into place instead
+    let template = dir.path().join("empty.db");
+    drop(db::open_or_create(&template).unwrap());
+    let output = dir.path().join("out").join("articles.db");
+    std::fs::create_dir_all(output.parent().unwrap()).unwrap();
+    let mut group = c.benchmark_group("extract");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_function(BenchmarkId::from_parameter(count), |b| {
+        b.iter_batched(
+            || {
+                if output.exists() {
+                    db::remove_database(&output).unwrap();
+                }
+                std::fs::copy(&template, &output).unwrap();
+            },
+            |()| {
+                // Syncing only measures the disk, so it is explicitly turned off
+                let args = ExtractArgs::parse_from([
+                    OsStr::new("extract"),
+                    OsStr::new("--out"),
+                    output.as_os_str(),
+                    OsStr::new("--no-progress"),
+                    OsStr::new("--no-cachedir-tag"),
+                    OsStr::new("--synchronous"),
+                    OsStr::new("off"),
+                    dump.as_os_str(),
+                ]);
+                wikipedia_html_extractor::extract::sql::extract(args.command).unwrap()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse, compress, extract);
+criterion_main!(benches);
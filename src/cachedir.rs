@@ -0,0 +1,64 @@
+//! Marks output directories as regenerable, so backup tools can skip them.
+//!
+//! See <https://bford.info/cachedir/> for the `CACHEDIR.TAG` convention.
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// The name of the cache directory tag
+pub const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+/// The name of the metadata file written next to the tag
+pub const EXTRACTION_META: &str = "extraction-meta.json";
+
+/// The contents of the tag, which must start with the standard signature
+const CACHEDIR_TAG_CONTENTS: &str = "Signature: 8a477f597d28d172789f06886806bc55
+# This file is a cache directory tag created by wikipedia-html-extractor.
+# The contents of this directory are a regenerable extraction of a Wikipedia dump.
+# For information about cache directory tags, see https://bford.info/cachedir/
+";
+
+/// Metadata about the extraction which produced an output directory
+#[derive(Debug, Serialize)]
+pub struct ExtractionMeta {
+    /// The command line of the extraction
+    pub command: Vec<String>,
+    /// When the extraction finished (in seconds since the epoch)
+    pub timestamp: u64,
+    /// The source files of the extraction
+    pub sources: Vec<String>,
+    /// The number of articles that were extracted (by this run)
+    pub articles: u64,
+}
+impl ExtractionMeta {
+    /// The metadata of the current process, which extracted the specified sources
+    pub fn new(sources: &[impl AsRef<Path>], articles: u64) -> Self {
+        ExtractionMeta {
+            command: std::env::args().collect(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            sources: sources
+                .iter()
+                .map(|source| source.as_ref().to_string_lossy().into_owned())
+                .collect(),
+            articles,
+        }
+    }
+}
+
+/// Write the `CACHEDIR.TAG` (unless it already exists) and the `extraction-meta.json`
+/// into the specified output directory.
+pub fn mark_output_dir(dir: &Path, meta: &ExtractionMeta) -> anyhow::Result<()> {
+    let tag = dir.join(CACHEDIR_TAG);
+    if !tag.exists() {
+        std::fs::write(&tag, CACHEDIR_TAG_CONTENTS)
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", tag.display(), e))?;
+    }
+    let meta_path = dir.join(EXTRACTION_META);
+    let mut json = serde_json::to_vec_pretty(meta)?;
+    json.push(b'\n');
+    std::fs::write(&meta_path, json)
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", meta_path.display(), e))?;
+    Ok(())
+}
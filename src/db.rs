@@ -0,0 +1,538 @@
+//! Shared helpers for the SQLite databases produced by the `extract` command
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::{FromSql, ToSql};
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use unicode_normalization::UnicodeNormalization;
+
+/// Marks an `article_body` as zstd compressed (the default)
+pub const ZSTD_COMPRESSION: &str = "zstd";
+/// Marks an `article_body` as raw uncompressed UTF-8
+pub const NO_COMPRESSION: &str = "none";
+
+/// The `meta` key of the number of articles that have been inserted.
+///
+/// This is updated in the same transaction as the inserts.
+pub const ARTICLE_COUNT_KEY: &str = "article_count";
+
+/// The version of the schema created by [open_or_create].
+///
+/// This is stored as the `user_version` of the database,
+/// and also recorded in the `meta` table.
+///
+/// Version 0 is the original schema, which declared the names and urls as `VARCHAR(255)`.
+/// SQLite ignores the declared length, so no data was actually lost,
+/// but changing the declared types would require rebuilding the tables.
+///
+/// Version 2 moved the uniqueness of the article names to their [NameKey].
+/// Older databases keep their unique names, and get a unique index of the keys
+/// (see [add_name_keys]).
+pub const SCHEMA_VERSION: i64 = 2;
+/// The `meta` key of the schema version
+pub const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// The `meta` key of the version of this crate which created the database
+pub const CRATE_VERSION_KEY: &str = "crate_version";
+/// The `meta` key of when the database was created (in seconds since the epoch)
+pub const CREATED_AT_KEY: &str = "created_at";
+/// The `meta` key of when articles were last extracted into the database
+/// (in seconds since the epoch)
+pub const EXTRACTED_AT_KEY: &str = "extracted_at";
+/// The `meta` key of the zstd level the bodies were compressed with (or `none`).
+///
+/// This is the level the database was created with, later extractions only warn if they differ.
+pub const COMPRESSION_LEVEL_KEY: &str = "compression_level";
+/// The `meta` key of the source files extracted into the database, as a JSON array
+pub const SOURCES_KEY: &str = "sources";
+/// The `meta` key of how the article names are normalized (see [NameKey]).
+///
+/// This is fixed when the database is created, since the keys are unique.
+pub const NAME_KEY_KEY: &str = "name_key";
+
+/// Create the `meta` key-value table, if it doesn't already exist
+pub fn create_meta_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS meta(key TEXT PRIMARY KEY, value);")
+}
+
+/// Set the value of the specified key in the `meta` table
+pub fn set_meta(conn: &Connection, key: &str, value: impl ToSql) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO meta(key, value) VALUES(?1, ?2)",
+        rusqlite::params![key, value],
+    )?;
+    Ok(())
+}
+
+/// Get the value of the specified key in the `meta` table
+pub fn get_meta<T: FromSql>(conn: &Connection, key: &str) -> rusqlite::Result<Option<T>> {
+    conn.query_row("SELECT value FROM meta WHERE key = ?1", [key], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+/// Columns added after the original schema,
+/// which are added to older databases when they are opened.
+///
+/// Databases created before `--no-compression` only have zstd bodies.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    (
+        "article_body",
+        "compression",
+        "TEXT NOT NULL DEFAULT 'zstd'",
+    ),
+    ("article_body", "text", "TEXT"),
+    ("article", "page_id", "INTEGER"),
+    ("article", "namespace", "INTEGER"),
+    ("article_body", "content_sha256", "TEXT"),
+    ("article_body", "revision", "INTEGER NOT NULL DEFAULT 0"),
+    ("article", "lang", "TEXT"),
+    ("article", "summary", "TEXT"),
+    ("article", "word_count", "INTEGER"),
+    ("article", "reading_seconds", "INTEGER"),
+    ("article", "name_key", "TEXT"),
+];
+
+/// The unique index of the [NameKey] of the articles in databases older than version 2,
+/// which declared the names themselves as unique.
+const NAME_KEY_INDEX: &str = "article_idx_name_key_unique";
+
+/// The secondary indexes of the `article` and `article_body` tables.
+///
+/// These are maintained on every insert, so bulk loads into an empty database
+/// can defer creating them until the end (see [create_indexes]).
+///
+/// The index on `article_body.article_id` is needed to join the bodies to their articles.
+///
+/// Since version 2, the names are no longer unique, so they need their own index.
+const INDEXES: &[(&str, &str)] = &[
+    ("article_idx_name", "article(name)"),
+    ("article_idx_url", "article(url)"),
+    ("article_idx_page_id", "article(page_id)"),
+    ("article_body_idx_article_id", "article_body(article_id)"),
+];
+
+/// Open a database for writing, creating it (and the schema) if it doesn't exist.
+///
+/// The schema of older databases is upgraded (see [ADDED_COLUMNS] and [add_name_keys]).
+/// This doesn't create the secondary indexes (see [create_indexes]).
+pub fn open_or_create(path: &Path) -> anyhow::Result<Connection> {
+    if !path.is_file() {
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        )?;
+        connection.execute_batch(&format!(
+            "
+            PRAGMA foreign_keys = ON;
+            PRAGMA user_version = {version};
+            CREATE TABLE article(
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                url TEXT NOT NULL,
+                page_id INTEGER,
+                namespace INTEGER,
+                lang TEXT,
+                summary TEXT,
+                word_count INTEGER,
+                reading_seconds INTEGER,
+                name_key TEXT UNIQUE NOT NULL
+            );
+            CREATE TABLE article_body(
+                id INTEGER PRIMARY KEY,
+                article_id INTEGER NOT NULL,
+                compressed_html BLOB,
+                compression TEXT NOT NULL DEFAULT 'zstd',
+                text TEXT,
+                content_sha256 TEXT,
+                revision INTEGER NOT NULL DEFAULT 0,
+                FOREIGN KEY(article_id) REFERENCES article(id)
+            );
+        ",
+            version = SCHEMA_VERSION
+        ))?;
+        connection.close().map_err(|(_, err)| err)?;
+    }
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    check_schema(&connection, path)?;
+    connection.execute_batch(
+        "
+        PRAGMA foreign_keys = ON;
+        PRAGMA journal_mode = WAL;
+    ",
+    )?;
+    create_meta_table(&connection)?;
+    register_functions(&connection)?;
+    add_missing_columns(&connection)?;
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    add_name_keys(&connection, path)?;
+    connection.execute(
+        "INSERT OR IGNORE INTO meta(key, value) VALUES(?1, ?2)",
+        rusqlite::params![SCHEMA_VERSION_KEY, version],
+    )?;
+    if version == 0 {
+        warn!(
+            "{} uses an older schema (version {}), which declares names as VARCHAR(255)",
+            path.display(),
+            version
+        );
+        warn!("SQLite ignores the declared length, so long names are still stored intact");
+    }
+    Ok(connection)
+}
+
+/// Upgrade the schema of an older database, adding any missing [ADDED_COLUMNS]
+pub fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    for &(table, column, definition) in ADDED_COLUMNS {
+        if !has_column(conn, table, column)? {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {};",
+                table, column, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check if the database has its secondary indexes.
+///
+/// This is false for new databases and for interrupted loads which deferred them.
+pub fn has_indexes(conn: &Connection) -> rusqlite::Result<bool> {
+    has_index(conn, "article_idx_url")
+}
+
+/// Create the secondary indexes which don't already exist,
+/// returning the names of the created indexes.
+pub fn create_indexes(conn: &Connection) -> rusqlite::Result<Vec<&'static str>> {
+    let mut created = Vec::new();
+    for &(name, target) in INDEXES {
+        if !has_index(conn, name)? {
+            conn.execute_batch(&format!("CREATE INDEX {} ON {};", name, target))?;
+            created.push(name);
+        }
+    }
+    Ok(created)
+}
+
+/// Check if an index with the specified name exists
+pub fn has_index(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?1)",
+        [name],
+        |row| row.get(0),
+    )
+}
+
+/// Create the case-insensitive index of the article names (for `query --ignore-case`),
+/// if it doesn't already exist.
+///
+/// Like SQLite's `NOCASE` collation, this only ignores the case of ASCII letters.
+pub fn create_nocase_index(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS article_idx_name_nocase ON article(name COLLATE NOCASE);",
+    )
+}
+
+/// How the article names are normalized into their key (`article.name_key`),
+/// which articles are deduplicated by.
+///
+/// This is recorded in the `meta` table when the database is created (see [NAME_KEY_KEY]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKey {
+    /// The Unicode NFC normalization of the name,
+    /// so the composed `Café` and decomposed `Cafe\u{301}` have the same key.
+    ///
+    /// Names which only differ by case (like `Nice` and `NICE`) are distinct articles.
+    Exact,
+    /// The NFC normalization of the name with its case folded,
+    /// so names which only differ by case are also duplicates.
+    IgnoreCase,
+}
+impl NameKey {
+    /// The normalized key of the article name
+    pub fn key(self, name: &str) -> String {
+        let normalized = name.nfc().collect::<String>();
+        match self {
+            NameKey::Exact => normalized,
+            // NOTE: Case folding can denormalize the name again (like `ǰ`)
+            NameKey::IgnoreCase => caseless::default_case_fold_str(&normalized).nfc().collect(),
+        }
+    }
+    fn as_str(self) -> &'static str {
+        match self {
+            NameKey::Exact => "nfc",
+            NameKey::IgnoreCase => "nfc-casefold",
+        }
+    }
+    /// Load how the names of the database are normalized.
+    ///
+    /// Databases which don't record it use [NameKey::Exact].
+    pub fn load(conn: &Connection) -> rusqlite::Result<Self> {
+        if !has_table(conn, "main", "meta")? {
+            return Ok(NameKey::Exact);
+        }
+        let stored = get_meta::<String>(conn, NAME_KEY_KEY)?;
+        Ok(match stored.as_deref() {
+            Some(value) if value == NameKey::IgnoreCase.as_str() => NameKey::IgnoreCase,
+            _ => NameKey::Exact,
+        })
+    }
+    /// Decide how the names of a database (opened by [open_or_create]) are normalized.
+    ///
+    /// The requested normalization is recorded for a database without any articles.
+    /// Otherwise the recorded one is kept, since the keys of the existing articles
+    /// would have to be recomputed (and deduplicated again).
+    pub fn init(conn: &Connection, path: &Path, requested: NameKey) -> anyhow::Result<Self> {
+        let recorded = get_meta::<String>(conn, NAME_KEY_KEY)?.is_some();
+        let empty = !conn.query_row("SELECT EXISTS(SELECT 1 FROM article)", [], |row| {
+            row.get::<_, bool>(0)
+        })?;
+        let existing = NameKey::load(conn)?;
+        let name_key = if empty {
+            requested
+        } else if existing != requested && requested == NameKey::IgnoreCase {
+            bail!(
+                "{} already deduplicates names by their exact case (use --fresh to replace it)",
+                path.display()
+            );
+        } else {
+            if existing != requested {
+                info!("{} deduplicates names ignoring their case", path.display());
+            }
+            existing
+        };
+        if !recorded || name_key != existing {
+            set_meta(conn, NAME_KEY_KEY, name_key.as_str())?;
+            // The key function depends on the normalization of the database
+            register_functions(conn)?;
+        }
+        Ok(name_key)
+    }
+}
+
+/// Register the SQL functions used to maintain the database (like `name_key(name)`).
+///
+/// The `name_key` function uses the normalization of the database (see [NameKey::load]).
+pub fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    let name_key = NameKey::load(conn)?;
+    conn.create_scalar_function(
+        "name_key",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| Ok(name_key.key(&ctx.get::<String>(0)?)),
+    )
+}
+
+/// Check if the articles of the database have their [NameKey]
+pub fn has_name_keys(conn: &Connection) -> rusqlite::Result<bool> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(version >= 2 || has_index(conn, NAME_KEY_INDEX)?)
+}
+
+/// Upgrade a database older than version 2 to deduplicate the articles by their [NameKey],
+/// unless it already has been.
+///
+/// The names of these databases are still unique (which would require rebuilding the table
+/// to change), so this adds the keys and a unique index of them.
+/// If several existing articles have the same key, only the first one keeps it.
+///
+/// This requires the `name_key` column and function (see [register_functions]).
+pub fn add_name_keys(conn: &Connection, path: &Path) -> rusqlite::Result<()> {
+    if has_name_keys(conn)? {
+        return Ok(());
+    }
+    info!("Adding normalized names to {}", path.display());
+    conn.execute_batch("BEGIN")?;
+    let res = (|| {
+        conn.execute("UPDATE article SET name_key = name_key(name)", [])?;
+        let duplicates = conn.execute(
+            "UPDATE article SET name_key = NULL \
+            WHERE id NOT IN (SELECT MIN(id) FROM article GROUP BY name_key)",
+            [],
+        )?;
+        if duplicates > 0 {
+            warn!(
+                "{} articles have the same normalized name as an earlier article, \
+                so they won't be found by it",
+                duplicates
+            );
+        }
+        conn.execute_batch(&format!(
+            "CREATE UNIQUE INDEX {} ON article(name_key);",
+            NAME_KEY_INDEX
+        ))
+    })();
+    match res {
+        Ok(()) => conn.execute_batch("COMMIT"),
+        Err(e) => {
+            conn.execute_batch("ROLLBACK")?;
+            Err(e)
+        }
+    }
+}
+
+/// Create the full text search index of the article text (`article_fts`),
+/// if it doesn't already exist.
+///
+/// This is an FTS5 table whose rowid is the `article.id`.
+pub fn create_fts_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("CREATE VIRTUAL TABLE IF NOT EXISTS article_fts USING fts5(text);")
+}
+
+/// Create the table of internal links between articles, if it doesn't already exist
+pub fn create_wikilink_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS wikilink(
+            article_id INTEGER NOT NULL,
+            target_title TEXT NOT NULL,
+            FOREIGN KEY(article_id) REFERENCES article(id)
+        );
+        CREATE INDEX IF NOT EXISTS wikilink_idx_article_id ON wikilink(article_id);
+        CREATE INDEX IF NOT EXISTS wikilink_idx_target_title ON wikilink(target_title);
+    ",
+    )
+}
+
+/// The suffixes of the files SQLite keeps alongside a database
+const SIDECAR_SUFFIXES: &[&str] = &["-wal", "-shm", "-journal"];
+
+/// Delete a database, along with its write-ahead log (and other sidecar files)
+pub fn remove_database(path: &Path) -> std::io::Result<()> {
+    std::fs::remove_file(path)?;
+    for suffix in SIDECAR_SUFFIXES {
+        let mut sidecar = path.as_os_str().to_os_string();
+        sidecar.push(suffix);
+        match std::fs::remove_file(&sidecar) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The tables of every database produced by the `extract` command
+const REQUIRED_TABLES: &[&str] = &["article", "article_body"];
+
+/// Check that an existing database was produced by the `extract` command,
+/// and that its schema isn't newer than [SCHEMA_VERSION].
+///
+/// Older schemas are fine, since they are upgraded when opened.
+pub fn check_schema(conn: &Connection, path: &Path) -> anyhow::Result<()> {
+    let version: i64 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| anyhow!("{} is not a SQLite database: {}", path.display(), e))?;
+    for table in REQUIRED_TABLES {
+        if !has_table(conn, "main", table)? {
+            bail!(
+                "{} is not a wikipedia-html-extractor database (missing the {} table)",
+                path.display(),
+                table
+            );
+        }
+    }
+    if version > SCHEMA_VERSION {
+        bail!(
+            "{} has schema version {}, but only versions up to {} are supported",
+            path.display(),
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Open an existing database for writing
+pub fn open_existing(path: &Path) -> anyhow::Result<Connection> {
+    if !path.is_file() {
+        bail!("Database doesn't exist: {}", path.display());
+    }
+    Ok(Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_WRITE,
+    )?)
+}
+
+/// Open an existing database read-only
+pub fn open_readonly(path: &Path) -> anyhow::Result<Connection> {
+    if !path.is_file() {
+        bail!("Database doesn't exist: {}", path.display());
+    }
+    Ok(Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?)
+}
+
+/// Check if the specified table has a column with the given name
+pub fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    Ok(columns(conn, "main", table)?
+        .iter()
+        .any(|name| name == column))
+}
+
+/// List the names of the columns in the specified table (of the specified schema)
+pub fn columns(conn: &Connection, schema: &str, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA {}.table_info({})", schema, table))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>("name"))?;
+    names.collect()
+}
+
+/// Check if a table with the specified name exists (in the specified schema)
+pub fn has_table(conn: &Connection, schema: &str, table: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        &format!(
+            "SELECT EXISTS(SELECT 1 FROM {}.sqlite_master WHERE name = ?1)",
+            schema
+        ),
+        [table],
+        |row| row.get(0),
+    )
+}
+
+/// The SQL expression to select the specified column,
+/// or `fallback` if it is missing (because the database is older).
+pub fn column_or(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    fallback: &str,
+) -> rusqlite::Result<String> {
+    Ok(if has_column(conn, table, column)? {
+        format!("{}.{}", table, column)
+    } else {
+        fallback.into()
+    })
+}
+
+/// The SQL expression to select the compression marker of an `article_body`.
+///
+/// Databases created before the marker was added only contain zstd bodies.
+pub fn compression_expr(conn: &Connection) -> rusqlite::Result<String> {
+    column_or(conn, "article_body", "compression", "'zstd'")
+}
+
+/// The SHA-256 hash of the (uncompressed) HTML, as stored in `article_body.content_sha256`.
+///
+/// This is encoded as lowercase hex.
+pub fn content_sha256(html: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(html.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Decode a stored article body into HTML, respecting its compression marker
+pub fn decode_body(body: Vec<u8>, compression: &str) -> anyhow::Result<String> {
+    let raw = match compression {
+        ZSTD_COMPRESSION => zstd::decode_all(&*body)?,
+        NO_COMPRESSION => body,
+        _ => bail!("Unknown body compression: {:?}", compression),
+    };
+    String::from_utf8(raw).map_err(|e| anyhow!("Article body isn't valid UTF-8: {}", e))
+}
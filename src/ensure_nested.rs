@@ -1,43 +1,87 @@
-use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use anyhow::anyhow;
-use clap::Args;
+use anyhow::{anyhow, bail};
+use clap::{ArgEnum, Args};
+use log::{info, warn};
+
+use crate::nesting::{DirCache, NestingOptions};
+
+/// What to do when a nested file already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum OnCollision {
+    /// Leave the original file in place
+    Skip,
+    /// Append a numeric suffix to the name of the moved file
+    Rename,
+}
 
 #[derive(Debug, Args)]
 pub struct EnsureNested {
     /// The target directory to convert
     #[clap(required = true, parse(from_os_str))]
     target_dir: PathBuf,
+    /// The layout to nest the files in (must match the one used by `extract-files`)
+    #[clap(flatten)]
+    nesting: NestingOptions,
+    /// What to do when the nested file already exists
+    #[clap(long, arg_enum, default_value = "skip")]
+    on_collision: OnCollision,
+    /// The number of threads moving files (defaults to the number of CPUs)
+    #[clap(long = "workers", short = 'j')]
+    workers: Option<u32>,
+    /// Log the planned moves without performing them
+    #[clap(long)]
+    dry_run: bool,
 }
 
 pub fn main(cmd: EnsureNested) -> anyhow::Result<()> {
+    let workers = cmd.workers.unwrap_or_else(crate::default_workers);
+    if workers == 0 {
+        bail!("Must have at least one worker");
+    }
+    info!("Using {} workers", workers);
     let target_dir = cmd.target_dir;
     let iterdir = std::fs::read_dir(&target_dir)
         .map_err(|e| anyhow!("Unable to read directory {}: {}", target_dir.display(), e))?;
+    let nesting = Arc::new(cmd.nesting);
+    let on_collision = cmd.on_collision;
+    let dry_run = cmd.dry_run;
     let counter = Arc::new(AtomicU64::new(0));
-    let existing_dirs = Arc::new(Mutex::new(HashSet::<PathBuf>::new()));
+    let existing_dirs = Arc::new(DirCache::new());
     let (sender, receiver) = crossbeam::channel::bounded::<PathBuf>(500);
     let mut handles = Vec::new();
-    for _ in 0..15 {
+    for index in 0..workers {
         let target_dir = PathBuf::clone(&target_dir);
         let counter = Arc::clone(&counter);
         let receiver = receiver.clone();
         let existing_dirs = existing_dirs.clone();
-        handles.push(std::thread::spawn(move || {
-            while let Ok(target) = receiver.recv() {
-                process_file(&*counter, &*target_dir, &*existing_dirs, &*target);
-            }
-            drop(receiver);
-        }));
+        let nesting = Arc::clone(&nesting);
+        handles.push(
+            std::thread::Builder::new()
+                .name(format!("ensure-nested-worker-{}", index))
+                .spawn(move || {
+                    while let Ok(target) = receiver.recv() {
+                        process_file(
+                            &counter,
+                            &target_dir,
+                            &nesting,
+                            on_collision,
+                            dry_run,
+                            &existing_dirs,
+                            &target,
+                        );
+                    }
+                    drop(receiver);
+                })?,
+        );
     }
     for entry in iterdir {
         let entry = match entry {
             Ok(entry) => entry,
             Err(e) => {
-                eprintln!("WARNING: Failed to read entry: {}", e);
+                warn!("Failed to read entry: {}", e);
                 continue;
             }
         };
@@ -45,8 +89,8 @@ pub fn main(cmd: EnsureNested) -> anyhow::Result<()> {
         let ft = match entry.file_type() {
             Ok(ft) => ft,
             Err(e) => {
-                eprintln!(
-                    "WARNING: Failed to fetch file type of {}: {}",
+                warn!(
+                    "Failed to fetch file type of {}: {}",
                     original_path.display(),
                     e
                 );
@@ -62,72 +106,109 @@ pub fn main(cmd: EnsureNested) -> anyhow::Result<()> {
     for handle in handles {
         handle.join().unwrap();
     }
+    let moved = counter.load(Ordering::SeqCst);
+    if dry_run {
+        info!("Would move {} files", moved);
+    } else {
+        info!("Moved {} files", moved);
+    }
     Ok(())
 }
 
 fn process_file(
     i: &AtomicU64,
     target_dir: &Path,
-    existing_dirs: &Mutex<HashSet<PathBuf>>,
+    nesting: &NestingOptions,
+    on_collision: OnCollision,
+    dry_run: bool,
+    existing_dirs: &DirCache,
     original_path: &Path,
 ) {
     let name = match original_path.file_name() {
         Some(stem) => stem.to_string_lossy().into_owned(),
         None => {
-            eprintln!("WARNING: Path has no name: {}", original_path.display());
+            warn!("Path has no name: {}", original_path.display());
             return;
         }
     };
-    let mut target_file = PathBuf::from(target_dir);
-    let mut chars = name.chars();
-    if let Some(first) = chars.next() {
-        target_file.push(String::from(first));
-        if let Some(second) = chars.next() {
-            target_file.push(String::from(second));
+    let mut target_file = nesting.nested_dir(target_dir, &name);
+    if dry_run {
+        if existing_dirs.insert(&target_file) && !target_file.is_dir() {
+            info!("Would create directory {}", target_file.display());
         }
+    } else if let Err(e) = existing_dirs.create_dir_all(&target_file) {
+        warn!(
+            "Unable to create directory {}: {}",
+            target_file.display(),
+            e
+        );
+        return;
     }
-    let exists = {
-        let lock = existing_dirs.lock().unwrap();
-        lock.contains(&target_file)
-    };
-    if !exists {
-        match std::fs::create_dir_all(&target_file) {
-            Ok(()) => {
-                let mut lock = existing_dirs.lock().unwrap();
-                lock.insert(target_file.clone());
-                drop(lock)
-            }
-            Err(e) => {
-                eprintln!(
-                    "WARNING: Unable to create directory {}: {}",
-                    target_file.display(),
-                    e
+    target_file.push(&name);
+    if target_file.exists() {
+        match on_collision {
+            OnCollision::Skip => {
+                warn!(
+                    "Not moving {}, because {} already exists",
+                    original_path.display(),
+                    target_file.display()
                 );
                 return;
             }
+            OnCollision::Rename => {
+                let renamed = unused_name(&target_file);
+                warn!(
+                    "Moving {} to {}, because {} already exists",
+                    original_path.display(),
+                    renamed.display(),
+                    target_file.display()
+                );
+                target_file = renamed;
+            }
         }
     }
-    target_file.push(name);
-    match std::fs::rename(&original_path, &target_file) {
+    if dry_run {
+        info!(
+            "Would move {} to {}",
+            original_path.display(),
+            target_file.display()
+        );
+        i.fetch_add(1, Ordering::SeqCst);
+        return;
+    }
+    match std::fs::rename(original_path, &target_file) {
         Ok(()) => {}
         Err(e) => {
-            eprintln!(
-                "WARNING: Failed to rename {}: {}",
-                original_path.display(),
-                e
-            );
+            warn!("Failed to rename {}: {}", original_path.display(), e);
             return;
         }
     }
     let i = i.fetch_add(1, Ordering::SeqCst);
-    if i % 100 == 0 {
-        eprintln!("Moved {} files", i);
+    if i.is_multiple_of(100) {
+        info!("Moved {} files", i);
     }
-    if i % 500 == 0 {
-        eprintln!(
+    if i.is_multiple_of(500) {
+        info!(
             "Moved {} to {}",
             original_path.display(),
             target_file.display()
         );
     }
 }
+
+/// Find an unused path by appending a numeric suffix to the file stem,
+/// so `Foo.html` becomes `Foo-1.html` (or `Foo-2.html` and so on).
+fn unused_name(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1u64..)
+        .map(|i| path.with_file_name(format!("{}-{}{}", stem, i, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
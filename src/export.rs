@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use log::info;
+use serde::Serialize;
+
+use crate::db;
+use crate::extract::{Article, ArticleBody, Namespace};
+
+/// An exported article, along with the metadata stored in the database
+#[derive(Serialize)]
+struct ExportedArticle {
+    #[serde(flatten)]
+    article: Article,
+    /// The SHA-256 hash of the HTML (missing from older databases)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_sha256: Option<String>,
+    /// The number of words in the text (if extracted with `--word-count`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word_count: Option<u64>,
+    /// The estimated time to read the article (if extracted with `--word-count`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reading_seconds: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExportCommand {
+    /// The database to export
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// The output NDJSON file (defaults to stdout)
+    #[clap(long = "out", parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// The limit on the number of articles to export
+    #[clap(long)]
+    limit: Option<u64>,
+}
+
+pub fn main(command: ExportCommand) -> anyhow::Result<()> {
+    let conn = db::open_readonly(&command.database)?;
+    let out: Box<dyn Write> = match command.output {
+        Some(ref path) => Box::new(
+            File::create(path)
+                .map_err(|e| anyhow::anyhow!("Failed to create file {}: {}", path.display(), e))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut out = BufWriter::new(out);
+    let sql = format!(
+        "SELECT article.name, article.url, article_body.compressed_html, {}, {}, {}, {}, {}, {} \
+        FROM article JOIN article_body ON article_body.article_id = article.id \
+        ORDER BY article.id LIMIT ?1",
+        db::compression_expr(&conn)?,
+        db::column_or(&conn, "article", "page_id", "NULL")?,
+        db::column_or(&conn, "article", "namespace", "NULL")?,
+        db::column_or(&conn, "article_body", "content_sha256", "NULL")?,
+        db::column_or(&conn, "article", "word_count", "NULL")?,
+        db::column_or(&conn, "article", "reading_seconds", "NULL")?,
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    // A negative limit means no limit in SQLite
+    let limit = command.limit.map_or(-1, |limit| limit as i64);
+    let mut rows = stmt.query([limit])?;
+    let mut count = 0u64;
+    while let Some(row) = rows.next()? {
+        let compression: String = row.get(3)?;
+        let article = Article {
+            name: row.get(0)?,
+            url: row.get(1)?,
+            identifier: row.get(4)?,
+            namespace: row
+                .get::<_, Option<i64>>(5)?
+                .map(|identifier| Namespace { identifier }),
+            redirect: None,
+            body: ArticleBody {
+                html: db::decode_body(row.get(2)?, &compression)?,
+            },
+        };
+        let article = ExportedArticle {
+            article,
+            content_sha256: row.get(6)?,
+            word_count: row.get(7)?,
+            reading_seconds: row.get(8)?,
+        };
+        serde_json::to_writer(&mut out, &article)?;
+        out.write_all(b"\n")?;
+        count += 1;
+        if count.is_multiple_of(500) {
+            info!("Exported {} articles", count);
+        }
+    }
+    out.flush()?;
+    info!("Exported {} articles", count);
+    Ok(())
+}
@@ -1,35 +1,261 @@
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-use serde::Deserialize;
-use serde_json::StreamDeserializer;
+use bzip2::bufread::MultiBzDecoder;
+use clap::ArgEnum;
+use flate2::bufread::MultiGzDecoder;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
 
 pub mod files;
+pub mod filter;
+pub mod progress;
+pub mod sample;
 pub mod sql;
+pub mod titles;
+pub mod url;
+
+/// The special target name which reads from standard input
+pub const STDIN_TARGET: &str = "-";
+
+/// The magic bytes at the start of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// The magic bytes at the start of a bzip2 stream
+const BZIP2_MAGIC: [u8; 3] = *b"BZh";
+/// The magic bytes at the start of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// The statistics of an extraction, for library users (the commands log them instead)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtractStats {
+    /// The number of articles that were passed to the listener,
+    /// including any which it skipped (but not the ones dropped by `--skip` or `--limit`)
+    pub processed: u64,
+    /// The number of articles that were skipped (like duplicates or existing files)
+    pub skipped: u64,
+    /// The number of articles that were rejected by the listener (like by `--filter-title`),
+    /// which aren't counted as processed
+    pub filtered: u64,
+    /// The number of articles that failed to parse
+    pub errors: u64,
+    /// The number of articles with an empty (or missing) body,
+    /// which are counted as processed unless they are skipped (see [OnEmpty])
+    pub empty: u64,
+    /// The total size of the HTML that was read
+    pub bytes_in: u64,
+    /// The total size of the (possibly compressed) output that was written
+    pub bytes_out: u64,
+    /// The time since the extraction started
+    pub elapsed: Duration,
+}
+
+impl ExtractStats {
+    /// Log a summary of the outcome of every article, as a small table
+    pub fn log_summary(&self) {
+        info!("Summary:");
+        info!("  {:<10} {:>12}", "processed", self.processed);
+        info!("  {:<10} {:>12}", "skipped", self.skipped);
+        info!("  {:<10} {:>12}", "filtered", self.filtered);
+        info!("  {:<10} {:>12}", "errors", self.errors);
+        info!("  {:<10} {:>12}", "empty body", self.empty);
+    }
+}
+
+/// What to do with articles whose body is empty (or missing)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum OnEmpty {
+    /// Drop the article, without passing it to the listener
+    Skip,
+    /// Store the article like any other
+    Store,
+}
+
+/// A callback which is periodically passed the statistics of an extraction
+/// (see [ExtractState::with_progress])
+pub type ProgressCallback = Box<dyn Fn(&ExtractStats) + Send + Sync>;
+
+/// The number of articles between each call to the [ProgressCallback]
+pub const PROGRESS_INTERVAL: u64 = 100;
+
+/// The number of parse errors which are logged individually by [ParseErrorLog]
+const LOGGED_PARSE_ERRORS: u64 = 10;
+/// The number of parse errors between each summary, after the first [LOGGED_PARSE_ERRORS]
+const PARSE_ERROR_SUMMARY_INTERVAL: u64 = 10_000;
+
+/// Logs the parse errors of an extraction, without flooding the log on a corrupt dump.
+///
+/// The first few errors are logged individually,
+/// then they are collapsed into a periodic summary.
+#[derive(Debug, Default)]
+pub struct ParseErrorLog {
+    count: AtomicU64,
+}
+impl ParseErrorLog {
+    pub fn log(&self, original_file: &Path, cause: &anyhow::Error) {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if count <= LOGGED_PARSE_ERRORS {
+            error!("Unable to parse {}: {}", target_name(original_file), cause);
+            if count == LOGGED_PARSE_ERRORS {
+                warn!("Further parse errors will only be summarized");
+            }
+        } else if count.is_multiple_of(PARSE_ERROR_SUMMARY_INTERVAL) {
+            warn!("Skipped {} unparseable records", count);
+        }
+    }
+}
 
 pub struct ExtractState {
+    /// The number of articles that have been parsed (and accepted by the listener),
+    /// including the ones dropped by `skip` or past the `limit`
     count: AtomicU64,
+    /// The number of articles that have been passed to the listener
+    processed: AtomicU64,
+    /// The number of articles that failed to parse
+    errors: AtomicU64,
+    /// The number of articles that were skipped by the listener
+    skipped: AtomicU64,
+    /// The number of articles that were rejected by the listener
+    filtered: AtomicU64,
+    /// The number of articles with an empty body
+    empty: AtomicU64,
+    started: Instant,
+    /// The total size of the HTML that has been read
+    bytes_in: AtomicU64,
+    /// The total size of the (possibly compressed) output that has been written
+    bytes_out: AtomicU64,
     should_stop: AtomicBool,
+    /// The number of parsed articles to skip before passing them to the listener
+    skip: u64,
+    /// The limit on the number of articles passed to the listener
+    limit: Option<u64>,
+    /// The number of parse errors which aborts the extraction
+    max_errors: Option<u64>,
+    on_empty: OnEmpty,
     error: Mutex<Option<ExtractError>>,
     error_cond: Condvar,
+    progress: Option<ProgressCallback>,
 }
 impl ExtractState {
-    /// Get a count of the number of items that have been extracted
+    /// Get a count of the number of items that have been parsed,
+    /// including any dropped by the `skip` or past the `limit`
     #[inline]
     pub fn count(&self) -> u64 {
         self.count.load(Ordering::SeqCst)
     }
-    pub fn new() -> Self {
+    /// Get a count of the number of items that have been passed to the listener
+    #[inline]
+    pub fn processed(&self) -> u64 {
+        self.processed.load(Ordering::SeqCst)
+    }
+    /// Get the total size of the HTML that has been read
+    #[inline]
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+    /// Get the total size of the output that has been written
+    #[inline]
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+    /// Record that the specified number of bytes has been written
+    #[inline]
+    pub fn add_bytes_out(&self, amount: u64) {
+        self.bytes_out.fetch_add(amount, Ordering::Relaxed);
+    }
+    /// Record that an article which was passed to the listener has been discarded
+    /// (like one past a limit enforced by the listener), so it isn't counted as processed
+    #[inline]
+    pub fn discard(&self) {
+        self.processed.fetch_sub(1, Ordering::SeqCst);
+    }
+    /// Record that the listener has skipped an article
+    #[inline]
+    pub fn add_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Get the current statistics of the extraction
+    pub fn stats(&self) -> ExtractStats {
+        ExtractStats {
+            processed: self.processed(),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            empty: self.empty.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in(),
+            bytes_out: self.bytes_out(),
+            elapsed: self.started.elapsed(),
+        }
+    }
+    /// Create a state which skips the first `skip` parsed articles (across all targets).
+    ///
+    /// The skipped articles are still counted, so the first article passed to the listener
+    /// has the count `skip`.
+    pub fn with_skip(skip: u64) -> Self {
         ExtractState {
             count: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            filtered: AtomicU64::new(0),
+            empty: AtomicU64::new(0),
+            started: Instant::now(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
             should_stop: AtomicBool::new(false),
+            skip,
+            limit: None,
+            max_errors: None,
+            on_empty: OnEmpty::Store,
             error: Mutex::new(None),
             error_cond: Condvar::new(),
+            progress: None,
         }
     }
+    /// Stop the extraction once the specified number of articles
+    /// have been passed to the listener (after any skipped ones).
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    /// Abort the extraction with [ExtractError::TooManyErrors]
+    /// once the specified number of articles have failed to parse.
+    pub fn with_max_errors(mut self, max_errors: u64) -> Self {
+        self.max_errors = Some(max_errors);
+        self
+    }
+    /// Decide what to do with the articles whose body is empty
+    /// (or only whitespace), which are stored by default.
+    pub fn with_on_empty(mut self, on_empty: OnEmpty) -> Self {
+        self.on_empty = on_empty;
+        self
+    }
+    /// Call the specified callback with the statistics
+    /// after every [PROGRESS_INTERVAL] articles passed to the listener.
+    ///
+    /// This is called from the extracting threads, so it should be quick.
+    pub fn with_progress(
+        mut self,
+        callback: impl Fn(&ExtractStats) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+    /// Check if the extraction has been stopped (because of an error or an interrupt)
+    #[inline]
+    pub fn is_stopped(&self) -> bool {
+        self.should_stop.load(Ordering::SeqCst)
+    }
+    /// Stop the extraction without an error.
+    ///
+    /// The workers finish the article they're currently processing, then return early.
+    pub fn stop(&self) {
+        self.should_stop.store(true, Ordering::SeqCst);
+    }
     fn provide_error(&self, error: ExtractError) {
         let mut lock = self.error.lock().unwrap();
         if lock.is_none() {
@@ -37,42 +263,552 @@ impl ExtractState {
         }
         self.error_cond.notify_all();
     }
+    /// Extract all the articles from the specified target.
+    ///
+    /// This returns early (successfully) if the extraction is stopped.
     pub fn run_extract(
         &self,
         target: PathBuf,
         listener: &dyn ExtractListener,
-    ) -> Result<(), ExtractError> {
-        let f = File::open(&target).map_err(|cause| ExtractError::FileIo {
-            target: target.clone(),
-            cause,
-        })?;
-        let f = BufReader::new(f);
-        let stream: StreamDeserializer<_, Article> =
-            serde_json::de::Deserializer::from_reader(f).into_iter();
-        for value in stream {
-            if self.should_stop.load(Ordering::SeqCst) {
-                return Ok(());
+    ) -> Result<TargetOutcome, ExtractError> {
+        let reader = ArticleReader::open(&target)?;
+        self.extract_reader(&target, reader, listener)
+    }
+    /// Extract all the articles from a target which has already been opened
+    /// (see [ExtractState::run_extract]).
+    pub fn extract_reader(
+        &self,
+        target: &Path,
+        mut reader: ArticleReader,
+        listener: &dyn ExtractListener,
+    ) -> Result<TargetOutcome, ExtractError> {
+        let mut outcome = TargetOutcome {
+            extracted: 0,
+            errors: 0,
+            finished: false,
+        };
+        for value in reader.by_ref() {
+            if self.is_stopped() {
+                return Ok(outcome);
             }
             match value {
+                Ok(article) if !listener.accept(&article) => {
+                    self.filtered.fetch_add(1, Ordering::Relaxed);
+                }
                 Ok(article) => {
+                    if article.body.html.trim().is_empty() {
+                        self.empty.fetch_add(1, Ordering::Relaxed);
+                        if self.on_empty == OnEmpty::Skip {
+                            debug!("Skipping {:?}, which has an empty body", article.name);
+                            continue;
+                        }
+                    }
                     let count = self.count.fetch_add(1, Ordering::SeqCst);
-                    listener
-                        .on_parse(ParseEvent {
-                            original_file: &target,
-                            count,
-                            article,
-                        })
-                        .map_err(ExtractError::Listener)?;
+                    if count < self.skip {
+                        continue;
+                    }
+                    if self.limit.is_some_and(|limit| count - self.skip >= limit) {
+                        self.stop();
+                        return Ok(outcome);
+                    }
+                    self.bytes_in
+                        .fetch_add(article.body.html.len() as u64, Ordering::Relaxed);
+                    // NOTE: Counted first, since the listener may discard it again
+                    self.processed.fetch_add(1, Ordering::SeqCst);
+                    let res = listener.on_parse(ParseEvent {
+                        original_file: target,
+                        count,
+                        article,
+                    });
+                    if let Err(cause) = res {
+                        // Rejected (like past the limit of the files extractor)
+                        self.discard();
+                        return Err(ExtractError::Listener(cause));
+                    }
+                    outcome.extracted += 1;
+                    if let Some(ref progress) = self.progress {
+                        if count.is_multiple_of(PROGRESS_INTERVAL) {
+                            progress(&self.stats());
+                        }
+                    }
                 }
-                Err(cause) => {
+                Err(cause @ ExtractError::Parse { .. }) => {
+                    let errors = self.errors.fetch_add(1, Ordering::Relaxed) + 1;
+                    outcome.errors += 1;
                     listener
-                        .on_parse_error(&target, cause.into())
+                        .on_parse_error(target, cause.into())
                         .map_err(ExtractError::Listener)?;
+                    if self.max_errors.is_some_and(|max| errors >= max) {
+                        self.stop();
+                        return Err(ExtractError::TooManyErrors { errors });
+                    }
                     continue;
                 }
+                Err(cause) => return Err(cause),
             }
         }
-        Ok(())
+        match reader.stopped_at() {
+            Some(offset) => warn!(
+                "Stopped reading {} at byte {}, the rest of the file was skipped",
+                target_name(target),
+                offset
+            ),
+            None => outcome.finished = true,
+        }
+        Ok(outcome)
+    }
+}
+
+/// The outcome of extracting a single target (see [ExtractState::run_extract])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetOutcome {
+    /// The number of articles that were passed to the listener
+    pub extracted: u64,
+    /// The number of records that failed to parse
+    pub errors: u64,
+    /// Whether the end of the target was reached,
+    /// without the extraction being stopped or an error cutting it short
+    pub finished: bool,
+}
+impl TargetOutcome {
+    /// Check if every record of the target was read and parsed successfully
+    #[inline]
+    pub fn is_clean(&self) -> bool {
+        self.finished && self.errors == 0
+    }
+}
+
+/// Reads the articles from a single (possibly compressed) NDJSON dump,
+/// without any of the side effects of the extract commands.
+///
+/// A dump which is a single top-level JSON array of articles is also accepted
+/// (detected by the leading `[`), and its elements are read one at a time.
+///
+/// This is an iterator over the parsed articles.
+/// An article which fails to parse is returned as an [ExtractError::Parse]
+/// (with its position in the file).
+/// For NDJSON, reading continues at the next line after the invalid record.
+/// An error which can't be recovered from (like an invalid record in an array,
+/// or corrupt compressed data) ends the iteration early, see [ArticleReader::stopped_at].
+pub struct ArticleReader {
+    stream: ArticleStream,
+    /// The number of records which have been read
+    records: u64,
+}
+enum ArticleStream {
+    /// Newline-delimited articles
+    Ndjson(NdjsonRecords),
+    /// The elements of a top-level array
+    Array(ArrayElements),
+}
+
+/// The UTF-8 byte order mark, which is skipped at the start of a dump
+const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+/// Streams newline-delimited records, a line at a time.
+///
+/// A record may continue over several lines (like pretty-printed JSON),
+/// and several records may share a line.
+struct NdjsonRecords {
+    reader: Box<dyn BufRead + Send>,
+    /// The lines which are currently being parsed
+    buf: Vec<u8>,
+    /// The position of the next record within `buf`
+    pos: usize,
+    /// The offset of the start of `buf` within the (decompressed) stream
+    buf_offset: u64,
+    /// The offset where reading stopped early (because of an IO error)
+    stopped_at: Option<u64>,
+    eof: bool,
+}
+impl NdjsonRecords {
+    /// Read the next line onto the end of the buffer,
+    /// returning `false` at the end of the stream
+    fn read_line(&mut self) -> io::Result<bool> {
+        if self.pos == self.buf.len() {
+            self.buf_offset += self.buf.len() as u64;
+            self.buf.clear();
+            self.pos = 0;
+        }
+        let read = self.reader.read_until(b'\n', &mut self.buf)?;
+        self.eof = read == 0;
+        Ok(!self.eof)
+    }
+    /// Parse the next record, along with its offset in the stream
+    fn next_record(&mut self) -> Option<(Result<Article, serde_json::Error>, u64)> {
+        if self.stopped_at.is_some() {
+            return None;
+        }
+        loop {
+            let whitespace = self.buf[self.pos..]
+                .iter()
+                .take_while(|b| b.is_ascii_whitespace())
+                .count();
+            self.pos += whitespace;
+            let offset = self.buf_offset + self.pos as u64;
+            if self.pos == self.buf.len() {
+                match self.read_line() {
+                    Ok(true) => continue,
+                    Ok(false) => return None,
+                    Err(cause) => {
+                        self.stopped_at = Some(offset);
+                        self.eof = true;
+                        return Some((Err(serde_json::Error::io(cause)), offset));
+                    }
+                }
+            }
+            let mut stream =
+                serde_json::Deserializer::from_slice(&self.buf[self.pos..]).into_iter::<Article>();
+            match stream.next() {
+                Some(Ok(article)) => {
+                    self.pos += stream.byte_offset();
+                    return Some((Ok(article), offset));
+                }
+                Some(Err(cause)) if cause.is_eof() && !self.eof => {
+                    // The record continues on the next line
+                    if let Err(cause) = self.read_line() {
+                        self.stopped_at = Some(offset);
+                        self.eof = true;
+                        return Some((Err(serde_json::Error::io(cause)), offset));
+                    }
+                }
+                Some(Err(cause)) => {
+                    self.pos += self.resync_len(&cause);
+                    return Some((Err(cause), offset));
+                }
+                None => unreachable!("whitespace was skipped"),
+            }
+        }
+    }
+    /// The number of bytes to skip after an invalid record (starting at `pos`).
+    ///
+    /// If the error is on the first line of the record, the rest of that line is skipped.
+    /// Otherwise the record was cut short, so parsing restarts at the line with the error,
+    /// which is likely the start of the next record.
+    fn resync_len(&self, cause: &serde_json::Error) -> usize {
+        let remaining = &self.buf[self.pos..];
+        let skipped_lines = cause.line().max(2) - 1;
+        remaining
+            .iter()
+            .enumerate()
+            .filter(|&(_, &b)| b == b'\n')
+            .nth(skipped_lines - 1)
+            .map_or(remaining.len(), |(idx, _)| idx + 1)
+    }
+}
+
+/// Streams the elements of a JSON array, after the opening `[` has been consumed
+struct ArrayElements {
+    reader: CountingReader,
+    first: bool,
+    /// The offset where reading stopped early
+    stopped_at: Option<u64>,
+    done: bool,
+}
+impl ArrayElements {
+    fn next_element(&mut self) -> Result<Option<Article>, serde_json::Error> {
+        let mut next = skip_whitespace(&mut self.reader).map_err(serde_json::Error::io)?;
+        if next == Some(b']') {
+            self.reader.consume(1);
+            return Ok(None);
+        }
+        if !self.first {
+            if next != Some(b',') {
+                return Err(serde::de::Error::custom("expected `,` or `]` in array"));
+            }
+            self.reader.consume(1);
+            next = skip_whitespace(&mut self.reader).map_err(serde_json::Error::io)?;
+        }
+        if next.is_none() {
+            return Err(serde::de::Error::custom("EOF while parsing array"));
+        }
+        self.first = false;
+        // NOTE: Objects end at their closing brace, so this never reads past the element
+        let mut de = serde_json::Deserializer::from_reader(&mut self.reader);
+        Article::deserialize(&mut de).map(Some)
+    }
+}
+impl Iterator for ArrayElements {
+    type Item = (Result<Article, serde_json::Error>, u64);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let offset = self.reader.consumed;
+        let res = self.next_element().transpose();
+        if !matches!(res, Some(Ok(_))) {
+            // The position within the array is lost after an error
+            self.done = true;
+            if res.is_some() {
+                self.stopped_at = Some(offset);
+            }
+        }
+        Some((res?, offset))
+    }
+}
+
+/// Counts the bytes which have been consumed from a reader
+struct CountingReader {
+    inner: Box<dyn BufRead + Send>,
+    consumed: u64,
+}
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.consumed += read as u64;
+        Ok(read)
+    }
+}
+impl BufRead for CountingReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.consumed += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+/// Skip the UTF-8 byte order mark at the start of a dump (if present),
+/// returning the number of bytes that were skipped.
+///
+/// Dumps re-saved on Windows often start with one, which isn't valid JSON.
+/// The CRLF line endings they have are fine, since `\r` is whitespace.
+pub fn skip_bom(reader: &mut dyn BufRead) -> io::Result<usize> {
+    if reader.fill_buf()?.starts_with(&UTF8_BOM) {
+        reader.consume(UTF8_BOM.len());
+        Ok(UTF8_BOM.len())
+    } else {
+        Ok(0)
+    }
+}
+
+/// Skip any whitespace, returning the next byte (without consuming it)
+fn skip_whitespace(reader: &mut dyn BufRead) -> io::Result<Option<u8>> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let whitespace = buf.iter().take_while(|b| b.is_ascii_whitespace()).count();
+        let next = buf.get(whitespace).copied();
+        reader.consume(whitespace);
+        if next.is_some() {
+            return Ok(next);
+        }
+    }
+}
+impl ArticleReader {
+    /// Open the specified target, transparently decompressing it (see [open_target]).
+    ///
+    /// A target of `-` reads from standard input.
+    pub fn open(target: &Path) -> Result<Self, ExtractError> {
+        let reader = open_target(target).map_err(|cause| ExtractError::FileIo {
+            target: target.to_path_buf(),
+            cause,
+        })?;
+        Self::from_buf_read(reader).map_err(|cause| ExtractError::FileIo {
+            target: target.to_path_buf(),
+            cause,
+        })
+    }
+    /// Read the articles from the specified reader, transparently decompressing it
+    /// (see [open_decompressed] for the supported formats).
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> io::Result<Self> {
+        Self::from_buf_read(decompress(BufReader::new(reader))?)
+    }
+    fn from_buf_read(reader: Box<dyn BufRead + Send>) -> io::Result<Self> {
+        let mut reader = CountingReader {
+            inner: reader,
+            consumed: 0,
+        };
+        skip_bom(&mut reader)?;
+        let stream = if skip_whitespace(&mut reader)? == Some(b'[') {
+            reader.consume(1);
+            ArticleStream::Array(ArrayElements {
+                reader,
+                first: true,
+                stopped_at: None,
+                done: false,
+            })
+        } else {
+            ArticleStream::Ndjson(NdjsonRecords {
+                buf_offset: reader.consumed,
+                reader: reader.inner,
+                buf: Vec::new(),
+                pos: 0,
+                stopped_at: None,
+                eof: false,
+            })
+        };
+        Ok(ArticleReader { stream, records: 0 })
+    }
+    /// The offset in the (decompressed) file where reading stopped early,
+    /// because of an error which can't be recovered from.
+    ///
+    /// This is `None` if the reader hasn't stopped early (yet).
+    pub fn stopped_at(&self) -> Option<u64> {
+        match self.stream {
+            ArticleStream::Ndjson(ref records) => records.stopped_at,
+            ArticleStream::Array(ref elements) => elements.stopped_at,
+        }
+    }
+}
+impl Iterator for ArticleReader {
+    type Item = Result<Article, ExtractError>;
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let (res, byte_offset) = match self.stream {
+            ArticleStream::Ndjson(ref mut records) => records.next_record()?,
+            ArticleStream::Array(ref mut elements) => elements.next()?,
+        };
+        self.records += 1;
+        Some(res.map_err(|cause| ExtractError::Parse {
+            cause,
+            record: self.records,
+            byte_offset,
+        }))
+    }
+}
+
+/// Check if the specified target refers to standard input (`-`)
+#[inline]
+pub fn is_stdin(target: &Path) -> bool {
+    target.as_os_str() == STDIN_TARGET
+}
+
+/// A human-readable name for the target, used in progress messages.
+///
+/// This is `<stdin>` for standard input.
+pub fn target_name(target: &Path) -> Cow<'_, str> {
+    if is_stdin(target) {
+        Cow::Borrowed("<stdin>")
+    } else {
+        target.to_string_lossy()
+    }
+}
+
+/// The extensions of the files discovered in a directory target
+const INPUT_EXTENSIONS: &[&str] = &["json", "ndjson", "jsonl"];
+/// The extensions of compressed input files (following one of the [INPUT_EXTENSIONS])
+const COMPRESSED_EXTENSIONS: &[&str] = &["gz", "bz2", "zst", "zstd"];
+
+/// Expand the targets given on the command line into the files to process.
+///
+/// A directory is expanded into all the files beneath it (recursively)
+/// which have one of the known extensions (`.json`, `.ndjson`, `.jsonl`,
+/// optionally followed by `.gz`, `.bz2` or `.zst`).
+/// A target which doesn't exist is treated as a glob pattern (like `dump/*.ndjson.gz`),
+/// so that patterns work even when the shell doesn't expand them.
+/// Other targets (including `-`) are passed through unchanged.
+pub fn discover_targets(targets: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut discovered = Vec::with_capacity(targets.len());
+    for target in targets {
+        if is_stdin(target) || target.is_file() {
+            discovered.push(target.clone());
+        } else if target.is_dir() {
+            discover_dir(target, &mut discovered)?;
+        } else {
+            let pattern = target.to_string_lossy();
+            let mut matched = false;
+            for path in glob::glob(&pattern)
+                .map_err(|e| anyhow::anyhow!("Invalid pattern {:?}: {}", pattern, e))?
+            {
+                let path = path?;
+                matched = true;
+                if path.is_dir() {
+                    discover_dir(&path, &mut discovered)?;
+                } else {
+                    discovered.push(path);
+                }
+            }
+            if !matched {
+                // NOTE: Not an error here, so a missing file gets the usual error message
+                discovered.push(target.clone());
+            }
+        }
+    }
+    info!("Discovered {} input files", discovered.len());
+    Ok(discovered)
+}
+
+/// Recursively discover the input files in the directory, in a consistent order
+fn discover_dir(dir: &Path, discovered: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in walkdir::WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() && is_input_file(entry.path()) {
+            discovered.push(entry.into_path());
+        }
+    }
+    Ok(())
+}
+
+/// Check if the file has one of the known input extensions
+fn is_input_file(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    let mut parts = name.rsplit('.');
+    let mut extension = parts.next();
+    if extension.is_some_and(|ext| COMPRESSED_EXTENSIONS.contains(&ext)) {
+        extension = parts.next();
+    }
+    // The remaining part is the stem, which must be present
+    parts.next().is_some_and(|stem| !stem.is_empty())
+        && extension.is_some_and(|ext| INPUT_EXTENSIONS.contains(&ext))
+}
+
+/// Open the specified target for extraction, transparently decompressing it.
+///
+/// A target of `-` reads from standard input.
+/// See [open_decompressed] for the supported compression formats.
+pub fn open_target(target: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    if is_stdin(target) {
+        decompress(BufReader::new(io::stdin()))
+    } else {
+        open_decompressed(target)
+    }
+}
+
+/// Open the specified input file, transparently decompressing it.
+///
+/// The compression format is detected by peeking at the leading bytes of the file.
+/// The following magic bytes are recognized:
+///
+/// | Format | Magic bytes           |
+/// |--------|-----------------------|
+/// | gzip   | `1f 8b`               |
+/// | bzip2  | `42 5a 68` (`"BZh"`)  |
+/// | zstd   | `28 b5 2f fd`         |
+///
+/// Anything else (including files too short to contain a magic)
+/// falls back to being read as plain uncompressed NDJSON.
+pub fn open_decompressed(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+/// Check if the leading bytes of a file have the magic of a supported compression format
+pub fn is_compressed(header: &[u8]) -> bool {
+    header.starts_with(&GZIP_MAGIC)
+        || header.starts_with(&BZIP2_MAGIC)
+        || header.starts_with(&ZSTD_MAGIC)
+}
+
+fn decompress<R: BufRead + Send + 'static>(mut reader: R) -> io::Result<Box<dyn BufRead + Send>> {
+    // NOTE: fill_buf doesn't consume anything, so the decoder still sees the magic
+    let header = reader.fill_buf()?;
+    if header.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if header.starts_with(&BZIP2_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiBzDecoder::new(reader))))
+    } else if header.starts_with(&ZSTD_MAGIC) {
+        // The streaming decoder continues across multiple frames
+        Ok(Box::new(BufReader::new(zstd::Decoder::with_buffer(
+            reader,
+        )?)))
+    } else {
+        Ok(Box::new(reader))
     }
 }
 
@@ -85,7 +821,12 @@ impl ThreadedExtractTask {
     /// Get a count of the number of items that had been extracted
     #[inline]
     pub fn count(&self) -> u64 {
-        self.state.count()
+        self.stats().processed
+    }
+    /// Get the current statistics of the extraction
+    #[inline]
+    pub fn stats(&self) -> ExtractStats {
+        self.state.stats()
     }
     #[inline]
     pub fn is_finished(&self) -> bool {
@@ -93,11 +834,8 @@ impl ThreadedExtractTask {
     }
     pub fn wait(&mut self) -> Result<(), ExtractError> {
         for handle in std::mem::take(&mut self.handles) {
-            match handle.join() {
-                Err(_) => {
-                    self.state.provide_error(ExtractError::UnexpectedPanic);
-                }
-                Ok(()) => {}
+            if handle.join().is_err() {
+                self.state.provide_error(ExtractError::UnexpectedPanic);
             }
             let mut lock = self.state.error.lock().unwrap();
             if lock.is_some() {
@@ -110,11 +848,25 @@ impl ThreadedExtractTask {
 
 #[derive(Debug, thiserror::Error)]
 pub enum ExtractError {
-    #[error("Fatal IO Error in {target}: {cause}")]
+    #[error("Fatal IO Error in {}: {cause}", target_name(target))]
     FileIo {
         target: PathBuf,
         cause: std::io::Error,
     },
+    /// An article failed to parse
+    ///
+    /// The line and column of the cause are relative to the start of the record.
+    #[error("Invalid article at record {record} (byte {byte_offset}): {cause}")]
+    Parse {
+        cause: serde_json::Error,
+        /// The (one-based) number of the record within the file
+        record: u64,
+        /// The offset of the record in the (decompressed) file
+        byte_offset: u64,
+    },
+    /// The extraction was aborted by [ExtractState::with_max_errors]
+    #[error("Aborting after {errors} parse errors")]
+    TooManyErrors { errors: u64 },
     #[error("Not a file: {}", target.display())]
     NotAFile { target: PathBuf },
     #[error("Unexpected panic in thread")]
@@ -124,6 +876,13 @@ pub enum ExtractError {
 }
 
 pub trait ExtractListener {
+    /// Check if the article should be extracted at all.
+    ///
+    /// Rejected articles are not counted and never passed to `on_parse`.
+    #[inline]
+    fn accept(&self, _article: &Article) -> bool {
+        true
+    }
     fn on_parse(&self, event: ParseEvent) -> Result<(), anyhow::Error>;
     fn on_parse_error(
         &self,
@@ -132,44 +891,201 @@ pub trait ExtractListener {
     ) -> Result<(), anyhow::Error>;
 }
 
+/// Extract the specified targets in parallel (one thread per target),
+/// passing the articles to the listener.
+///
+/// See [ExtractBuilder] for more options.
 pub fn extract_threaded(
     paths: Vec<PathBuf>,
+    state: ExtractState,
     listener: Box<dyn ExtractListener + Send + Sync + 'static>,
 ) -> Result<ThreadedExtractTask, ExtractError> {
-    let state = Arc::new(ExtractState::new());
+    let workers = paths.len();
+    spawn_workers(paths, workers, state, listener)
+}
+
+/// Configures an extraction, without going through the command line.
+///
+/// ```text
+/// let task = ExtractBuilder::new()
+///     .targets(paths)
+///     .workers(4)
+///     .limit(1000)
+///     .listener(Box::new(listener))
+///     .spawn()?;
+/// ```
+#[derive(Default)]
+pub struct ExtractBuilder {
+    targets: Vec<PathBuf>,
+    workers: Option<usize>,
+    skip: u64,
+    limit: Option<u64>,
+    max_errors: Option<u64>,
+    on_empty: Option<OnEmpty>,
+    progress: Option<ProgressCallback>,
+    listener: Option<Box<dyn ExtractListener + Send + Sync + 'static>>,
+}
+impl ExtractBuilder {
+    pub fn new() -> Self {
+        ExtractBuilder::default()
+    }
+    /// The files to extract (`-` reads from stdin)
+    pub fn targets(mut self, targets: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.targets.extend(targets);
+        self
+    }
+    /// The number of threads extracting the targets (defaults to one per target)
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+    /// Skip the first N parsed articles (see [ExtractState::with_skip])
+    pub fn skip(mut self, skip: u64) -> Self {
+        self.skip = skip;
+        self
+    }
+    /// The limit on the number of articles passed to the listener
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    /// Abort after N parse errors (see [ExtractState::with_max_errors])
+    pub fn max_errors(mut self, max_errors: u64) -> Self {
+        self.max_errors = Some(max_errors);
+        self
+    }
+    /// What to do with articles whose body is empty (see [ExtractState::with_on_empty])
+    pub fn on_empty(mut self, on_empty: OnEmpty) -> Self {
+        self.on_empty = Some(on_empty);
+        self
+    }
+    /// The callback to report progress to (see [ExtractState::with_progress])
+    pub fn progress(mut self, callback: impl Fn(&ExtractStats) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+    /// The listener which is passed the extracted articles
+    pub fn listener(mut self, listener: Box<dyn ExtractListener + Send + Sync + 'static>) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+    /// Start extracting in the background.
+    ///
+    /// # Panics
+    /// If no listener was specified.
+    pub fn spawn(self) -> Result<ThreadedExtractTask, ExtractError> {
+        let listener = self.listener.expect("ExtractBuilder requires a listener");
+        let mut state = ExtractState::with_skip(self.skip);
+        if let Some(limit) = self.limit {
+            state = state.with_limit(limit);
+        }
+        if let Some(max_errors) = self.max_errors {
+            state = state.with_max_errors(max_errors);
+        }
+        if let Some(on_empty) = self.on_empty {
+            state = state.with_on_empty(on_empty);
+        }
+        state.progress = self.progress;
+        let workers = self.workers.unwrap_or(self.targets.len()).max(1);
+        spawn_workers(self.targets, workers, state, listener)
+    }
+}
+
+/// Spawn the specified number of threads, which extract the targets in order
+fn spawn_workers(
+    paths: Vec<PathBuf>,
+    workers: usize,
+    state: ExtractState,
+    listener: Box<dyn ExtractListener + Send + Sync + 'static>,
+) -> Result<ThreadedExtractTask, ExtractError> {
+    if let Some(target) = paths
+        .iter()
+        .find(|target| !is_stdin(target) && !target.is_file())
+    {
+        return Err(ExtractError::NotAFile {
+            target: target.clone(),
+        });
+    }
+    let state = Arc::new(state);
     let mut task = ThreadedExtractTask {
         handles: Vec::new(),
         state: Arc::clone(&state),
         listener: Arc::from(listener),
     };
-    for target in paths {
-        if !target.is_file() {
-            return Err(ExtractError::NotAFile { target });
-        }
+    let queue = Arc::new(Mutex::new(paths.into_iter()));
+    for index in 0..workers {
         let state = Arc::clone(&state);
         let listener = Arc::clone(&task.listener);
-        let handle = std::thread::spawn(move || match state.run_extract(target, &*listener) {
-            Err(error) => {
-                state.should_stop.store(true, Ordering::SeqCst);
-                state.provide_error(error);
-            }
-            Ok(()) => {}
-        });
+        let queue = Arc::clone(&queue);
+        let handle = std::thread::Builder::new()
+            .name(format!("extract-worker-{}", index))
+            .spawn(move || loop {
+                let target = match queue.lock().unwrap().next() {
+                    Some(target) => target,
+                    None => break,
+                };
+                if state.is_stopped() {
+                    break;
+                }
+                if let Err(error) = state.run_extract(target, &*listener) {
+                    state.should_stop.store(true, Ordering::SeqCst);
+                    state.provide_error(error);
+                    break;
+                }
+            })
+            .expect("failed to spawn thread");
         task.handles.push(handle);
     }
     Ok(task)
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Article {
     pub name: String,
     pub url: String,
-    #[serde(rename = "article_body")]
+    /// The (stable) page id of the article
+    ///
+    /// This is missing from older dumps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<u64>,
+    /// The namespace of the article
+    ///
+    /// This is missing from older dumps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<Namespace>,
+    /// Whether the article is a redirect to another article
+    ///
+    /// This is missing from most dumps (see [Article::is_redirect]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<bool>,
+    /// The body of the article, which is empty if it is missing from the dump
+    #[serde(rename = "article_body", default)]
     pub body: ArticleBody,
 }
+impl Article {
+    /// Check if the article is a redirect to another article.
+    ///
+    /// For dumps without the `redirect` flag, this falls back to detecting
+    /// the redirect message (`<div class="redirectMsg">`) in the HTML.
+    pub fn is_redirect(&self) -> bool {
+        self.redirect
+            .unwrap_or_else(|| self.body.html.contains(REDIRECT_MARKER))
+    }
+}
 
-#[derive(Debug, Deserialize)]
+/// The marker of the message MediaWiki renders for redirect pages
+const REDIRECT_MARKER: &str = "class=\"redirectMsg\"";
+
+/// A MediaWiki namespace (main, Talk:, Template:, etc.)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Namespace {
+    /// The namespace id (zero for the main namespace)
+    pub identifier: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct ArticleBody {
+    #[serde(default)]
     pub html: String,
 }
 
@@ -179,11 +1095,21 @@ pub struct ParseEvent<'a> {
     pub article: Article,
 }
 
-pub fn basic_report_progress(count: u64, article_name: &str, verbose: bool) {
-    if count % 100 == 0 {
-        eprintln!("Processed {} files", count);
+/// The default [ProgressCallback], which logs the number of processed articles
+pub fn log_progress(stats: &ExtractStats) {
+    info!("Processed {} files", stats.processed);
+}
+
+/// Periodically log the progress of an extraction.
+///
+/// Every extracted article is logged at the debug level (`--verbose`).
+pub fn basic_report_progress(count: u64, article_name: &str) {
+    if count.is_multiple_of(100) {
+        info!("Processed {} files", count);
     }
-    if count % 500 == 0 || verbose {
-        eprintln!("Extracted {}", article_name);
+    if count.is_multiple_of(500) {
+        info!("Extracted {}", article_name);
+    } else {
+        debug!("Extracted {}", article_name);
     }
 }
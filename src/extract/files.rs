@@ -1,71 +1,156 @@
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
 };
 
-use clap::Args;
+use clap::{ArgEnum, Args};
+use log::{debug, error, info, warn};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
 
-use crate::extract::ExtractError;
+use crate::cachedir::ExtractionMeta;
+use crate::extract::filter::ArticleFilter;
+use crate::extract::url::{parse_url, ArticleUrl};
+use crate::extract::{ExtractBuilder, ExtractError, ExtractStats, OnEmpty, ParseErrorLog};
+use crate::nesting::{DirCache, NestingOptions};
 
 #[derive(Debug, thiserror::Error)]
 #[error("Cancelled extract")]
 struct CancelledError;
 
+/// How to turn article names into file names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Sanitize {
+    /// Replace `/`, `:` and `*` with `__`, `__colon__` and `__star__`
+    ///
+    /// This is lossy, since the replacements could also appear in the name itself.
+    /// Control characters are percent-encoded.
+    Legacy,
+    /// Percent-encode the bytes which are unsafe in file names
+    ///
+    /// This is reversible (see [desanitize_name]).
+    Percent,
+    /// Like `percent`, but also escape the device names reserved by Windows
+    ///
+    /// Names like `CON` or `nul.txt` can't be used as file names on Windows
+    /// (even with an extension), so the last character of the reserved name is
+    /// percent-encoded as well (`CON` becomes `CO%4E`).
+    /// This is still reversible.
+    Windows,
+}
+
 #[derive(Debug, Args)]
 pub struct ExtractCommand {
-    /// Output verbose information (print every file extracted)
-    #[clap(long)]
-    verbose: bool,
     /// The limit on the number of files to extract
     #[clap(long)]
     limit: Option<u64>,
+    /// Skip the first N parsed articles (after filtering) before extracting any
+    ///
+    /// Articles are counted in the order they are parsed. With multiple target files
+    /// (or workers), the files are parsed concurrently and their articles interleave
+    /// nondeterministically, so only a single target gives a reproducible window.
+    /// Combine with `--limit` to extract a window of articles.
+    #[clap(long, visible_alias = "offset", default_value = "0")]
+    skip: u64,
+    /// Abort the extraction once this many records have failed to parse
+    #[clap(long)]
+    max_errors: Option<u64>,
+    /// Exit successfully even if some records failed to parse
+    #[clap(long)]
+    ignore_errors: bool,
+    /// What to do with articles whose HTML is empty (or missing)
+    ///
+    /// Either way, they are counted in the "empty body" total of the summary.
+    #[clap(long, arg_enum, default_value = "store")]
+    on_empty: OnEmpty,
+    /// Don't write a `CACHEDIR.TAG` and `extraction-meta.json` into the output directory
+    ///
+    /// The tag tells backup tools that the contents are regenerable.
+    #[clap(long)]
+    no_cachedir_tag: bool,
     /// Skip existing files
     #[clap(long)]
     skip_existing: bool,
-    /// Do not nest the extracted files
-    #[clap(long)]
-    no_nesting: bool,
+    #[clap(flatten)]
+    layout: FileLayout,
     /// The target directory to extract files into
     #[clap(long = "out", parse(from_os_str))]
     output_dir: Option<PathBuf>,
-    /// The target files to extract
+    #[clap(flatten)]
+    filter: ArticleFilter,
+    /// The target files to extract (`-` reads from stdin)
+    ///
+    /// Directories and glob patterns are expanded into the input files they contain.
     #[clap(required = true, parse(from_os_str))]
     targets: Vec<PathBuf>,
 }
+
+// Where the article files are written (shared with the `to-files` command),
+// as a plain comment so clap doesn't show it in the help of both commands
+#[derive(Debug, Clone, Args)]
+pub struct FileLayout {
+    /// Do not nest the extracted files
+    #[clap(long)]
+    no_nesting: bool,
+    /// The layout to nest the files in (see the `ensure-nested` command)
+    #[clap(flatten)]
+    nesting: NestingOptions,
+    /// How to turn article names into file names
+    #[clap(long, arg_enum, default_value = "legacy")]
+    sanitize: Sanitize,
+    /// Nest the files under a directory for the language of their wiki (`de/F/o/Foo.html`)
+    ///
+    /// Relative urls (and urls which aren't on wikipedia) are nested under `unknown`.
+    #[clap(long)]
+    by_language: bool,
+}
+impl FileLayout {
+    /// The directory (under `base`) and the name of the file to write the article into
+    pub fn locate(&self, base: &Path, url: &ArticleUrl) -> (PathBuf, String) {
+        let name = format!("{}.html", sanitize_name(&url.title, self.sanitize));
+        let mut base_dir = base.to_path_buf();
+        if self.by_language {
+            base_dir.push(url.lang.as_deref().unwrap_or("unknown"));
+        }
+        let dir = if self.no_nesting {
+            base_dir
+        } else {
+            self.nesting.nested_dir(&base_dir, &name)
+        };
+        (dir, name)
+    }
+}
+
 struct FileExtractListener {
     command: ExtractCommand,
-    skipped: AtomicU64,
+    filter: Arc<ArticleFilter>,
+    skipped: Arc<AtomicU64>,
+    /// The total size of the files written
+    bytes_out: Arc<AtomicU64>,
+    /// The number of files written (or about to be written) by all the threads
+    written: Arc<AtomicU64>,
     target_dir: PathBuf,
+    existing_dirs: DirCache,
+    parse_errors: ParseErrorLog,
 }
 impl super::ExtractListener for FileExtractListener {
+    fn accept(&self, article: &super::Article) -> bool {
+        self.filter.accept(article)
+    }
     fn on_parse(&self, event: super::ParseEvent) -> Result<(), anyhow::Error> {
-        if let Some(limit) = self.command.limit {
-            if event.count >= limit {
-                return Err(CancelledError.into());
-            }
-        }
-        let name = match parse_url(&event.article.url) {
+        let url = match parse_url(&event.article.url) {
             Err(msg) => {
-                eprintln!("WARNING: {}", msg);
+                warn!("{}", msg);
                 return Ok(());
             }
-            Ok(name) => sanitize_name(&*name),
+            Ok(url) => url,
         };
-        let mut target_file = self.target_dir.clone();
-        let mut chars = name.chars();
-        if !self.command.no_nesting {
-            if let Some(first) = chars.next() {
-                target_file.push(String::from(first));
-                if let Some(second) = chars.next() {
-                    target_file.push(String::from(second));
-                }
-            }
-        }
-        match std::fs::create_dir_all(&target_file) {
+        let (mut target_file, name) = self.command.layout.locate(&self.target_dir, &url);
+        match self.existing_dirs.create_dir_all(&target_file) {
             Ok(()) => {}
             Err(e) => {
-                eprintln!(
-                    "WARNING: Unable to create directory {}: {}",
+                warn!(
+                    "Unable to create directory {}: {}",
                     target_file.display(),
                     e
                 );
@@ -75,22 +160,26 @@ impl super::ExtractListener for FileExtractListener {
         target_file.push(name);
         if self.command.skip_existing && target_file.is_file() {
             let i = self.skipped.fetch_add(1, Ordering::SeqCst);
-            if i % 500 == 0 {
-                eprintln!("Skipped {} files", i);
+            if i.is_multiple_of(500) {
+                info!("Skipped {} files", i);
             }
             return Ok(());
         }
+        // Reserve a slot before writing, so the threads never exceed the limit between them
+        let written = self.written.fetch_add(1, Ordering::SeqCst);
+        if self.command.limit.is_some_and(|limit| written >= limit) {
+            return Err(CancelledError.into());
+        }
         match std::fs::write(&target_file, event.article.body.html.as_bytes()) {
             Ok(()) => {
-                super::basic_report_progress(
-                    event.count,
-                    &event.article.name,
-                    self.command.verbose,
-                );
+                self.bytes_out
+                    .fetch_add(event.article.body.html.len() as u64, Ordering::Relaxed);
+                debug!("Extracted {}", event.article.name);
                 Ok(())
             }
             Err(e) => {
-                eprintln!("ERROR: Failed to write to {}: {}", target_file.display(), e);
+                self.written.fetch_sub(1, Ordering::SeqCst);
+                error!("Failed to write to {}: {}", target_file.display(), e);
                 Ok(())
             }
         }
@@ -98,16 +187,17 @@ impl super::ExtractListener for FileExtractListener {
 
     fn on_parse_error(
         &self,
-        _original_file: &std::path::Path,
+        original_file: &std::path::Path,
         cause: anyhow::Error,
     ) -> Result<(), anyhow::Error> {
-        eprintln!("ERROR: Unable to parse file: {}", cause);
+        self.parse_errors.log(original_file, &cause);
         Ok(())
     }
 }
-pub fn extract(command: ExtractCommand) -> anyhow::Result<()> {
-    eprintln!("WARNING: This command is deprecated. It overloads the FS");
-    eprintln!("Consider using the new `extract` command (uses SQLite)");
+/// Extract the articles into files, returning the statistics of the extraction
+pub fn extract(mut command: ExtractCommand) -> anyhow::Result<ExtractStats> {
+    warn!("This command is deprecated. It overloads the FS");
+    warn!("Consider using the new `extract` command (uses SQLite)");
     let target_dir = command
         .output_dir
         .clone()
@@ -115,33 +205,146 @@ pub fn extract(command: ExtractCommand) -> anyhow::Result<()> {
     if !target_dir.is_dir() {
         std::fs::create_dir(&target_dir)?;
     }
-    let paths = command.targets.clone();
+    let paths = super::discover_targets(&command.targets)?;
+    let skip = command.skip;
+    let cachedir_tag = !command.no_cachedir_tag;
+    let output_dir = target_dir.clone();
+    let max_errors = command.max_errors;
+    let ignore_errors = command.ignore_errors;
+    let on_empty = command.on_empty;
+    let filter = Arc::new(std::mem::take(&mut command.filter));
+    let limit = command.limit.unwrap_or(u64::MAX);
+    let written = Arc::new(AtomicU64::new(0));
+    let skipped = Arc::new(AtomicU64::new(0));
+    let bytes_out = Arc::new(AtomicU64::new(0));
     let listener = FileExtractListener {
         command,
-        skipped: AtomicU64::new(0),
+        filter: Arc::clone(&filter),
+        skipped: Arc::clone(&skipped),
+        bytes_out: Arc::clone(&bytes_out),
+        written: Arc::clone(&written),
         target_dir,
+        existing_dirs: DirCache::new(),
+        parse_errors: ParseErrorLog::default(),
     };
-    let mut task = super::extract_threaded(paths, Box::new(listener))?;
+    let mut builder = ExtractBuilder::new()
+        .targets(paths.clone())
+        .skip(skip)
+        .on_empty(on_empty);
+    if let Some(max_errors) = max_errors {
+        builder = builder.max_errors(max_errors);
+    }
+    let mut task = builder
+        .progress(super::log_progress)
+        .listener(Box::new(listener))
+        .spawn()?;
     match task.wait() {
         Ok(()) => {}
         Err(ExtractError::Listener(ref e)) if e.is::<CancelledError>() => {}
         Err(cause) => return Err(cause.into()),
     }
     assert!(task.is_finished());
-    eprintln!("Extracted {} files", task.count());
-    Ok(())
+    // NOTE: Cancelled threads may have reserved a slot past the limit
+    info!(
+        "Extracted {} files from {} parsed articles",
+        written.load(Ordering::SeqCst).min(limit),
+        task.count()
+    );
+    filter.report();
+    let stats = ExtractStats {
+        skipped: skipped.load(Ordering::SeqCst),
+        bytes_out: bytes_out.load(Ordering::Relaxed),
+        ..task.stats()
+    };
+    stats.log_summary();
+    if cachedir_tag {
+        let meta = ExtractionMeta::new(&paths, written.load(Ordering::SeqCst).min(limit));
+        crate::cachedir::mark_output_dir(&output_dir, &meta)?;
+    }
+    if stats.errors > 0 && !ignore_errors {
+        anyhow::bail!(
+            "Failed to parse {} records (use --ignore-errors to allow this)",
+            stats.errors
+        );
+    }
+    Ok(stats)
 }
 
-fn parse_url(url: &str) -> Result<String, String> {
-    const PREFIX: &str = "/wiki/";
-    match url.find(PREFIX) {
-        None => Err(format!("No `/wiki/` in {:?}", url)),
-        Some(idx) => Ok(format!("{}.html", &url[idx + PREFIX.len()..])),
+/// The bytes which are percent-encoded by [Sanitize::Percent].
+///
+/// This includes the characters reserved by Windows, and the `%` itself.
+const UNSAFE_FILENAME: &AsciiSet = &CONTROLS
+    .add(b'/')
+    .add(b'\\')
+    .add(b':')
+    .add(b'*')
+    .add(b'?')
+    .add(b'"')
+    .add(b'<')
+    .add(b'>')
+    .add(b'|')
+    .add(b'%');
+
+/// The device names reserved by Windows, which can't be used as (the stem of) a file name
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turn an article name into a file name, using the specified scheme
+pub fn sanitize_name(name: &str, scheme: Sanitize) -> String {
+    match scheme {
+        Sanitize::Legacy => name
+            .replace('/', "__")
+            .replace(':', "__colon__")
+            .replace('*', "__star__")
+            .chars()
+            .map(|c| {
+                if c.is_ascii_control() {
+                    format!("%{:02X}", c as u8)
+                } else {
+                    String::from(c)
+                }
+            })
+            .collect(),
+        Sanitize::Percent => utf8_percent_encode(name, UNSAFE_FILENAME).to_string(),
+        Sanitize::Windows => {
+            let encoded = utf8_percent_encode(name, UNSAFE_FILENAME).to_string();
+            // Windows ignores everything after the first dot when checking for device names
+            let stem_len = encoded.find('.').unwrap_or(encoded.len());
+            let stem = &encoded[..stem_len];
+            if WINDOWS_RESERVED
+                .iter()
+                .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+            {
+                // The reserved names are all ASCII
+                let last = stem.as_bytes()[stem_len - 1];
+                format!(
+                    "{}%{:02X}{}",
+                    &encoded[..stem_len - 1],
+                    last,
+                    &encoded[stem_len..]
+                )
+            } else {
+                encoded
+            }
+        }
     }
 }
 
-pub fn sanitize_name(name: &str) -> String {
-    name.replace('/', "__")
-        .replace(':', "__colon__")
-        .replace('*', "__star__")
+/// Recover the article name from a file name produced by [sanitize_name].
+///
+/// This is exact for [Sanitize::Percent] and [Sanitize::Windows].
+/// The [Sanitize::Legacy] scheme is lossy, so this only gives a best guess for it.
+pub fn desanitize_name(name: &str, scheme: Sanitize) -> anyhow::Result<String> {
+    Ok(match scheme {
+        Sanitize::Legacy => name
+            .replace("__colon__", ":")
+            .replace("__star__", "*")
+            .replace("__", "/"),
+        Sanitize::Percent | Sanitize::Windows => percent_decode_str(name)
+            .decode_utf8()
+            .map_err(|e| anyhow::anyhow!("Invalid UTF-8 in {:?}: {}", name, e))?
+            .into_owned(),
+    })
 }
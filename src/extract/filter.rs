@@ -0,0 +1,187 @@
+//! Filtering which articles are extracted
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use clap::Args;
+use log::{info, warn};
+use regex::Regex;
+
+use super::Article;
+
+// Decides which articles are extracted, shared between all the extract commands.
+// This isn't a doc comment, since clap would use it as the help of those commands.
+//
+// Articles that are filtered out are skipped before they are counted,
+// so they don't count towards `--limit`.
+#[derive(Debug, Default, Args)]
+pub struct ArticleFilter {
+    /// Only extract articles whose name matches this regex
+    ///
+    /// The match is unanchored (it can match anywhere in the name),
+    /// use `^` and `$` to match the whole name.
+    #[clap(long)]
+    filter_title: Option<Regex>,
+    /// Only extract articles in the specified namespace id (repeatable)
+    ///
+    /// The main namespace is 0, `Talk:` is 1 and `Template:` is 10.
+    /// For dumps without namespace information,
+    /// the namespace is guessed from the title prefix (`Talk:`, `Template:`, etc).
+    #[clap(
+        long = "namespace",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        allow_hyphen_values = true
+    )]
+    namespaces: Vec<i64>,
+    /// Skip redirect pages
+    ///
+    /// For dumps without a `redirect` flag, redirects are detected
+    /// by the redirect message in their HTML.
+    #[clap(long)]
+    skip_redirects: bool,
+    /// Skip articles whose HTML is larger than this many bytes
+    #[clap(long)]
+    max_html_bytes: Option<usize>,
+    /// Skip articles whose HTML is smaller than this many bytes (like stubs)
+    #[clap(long)]
+    min_html_bytes: Option<usize>,
+    /// The number of articles that have been filtered out
+    #[clap(skip)]
+    filtered: AtomicU64,
+    /// The number of articles that have been filtered out by namespace
+    #[clap(skip)]
+    filtered_namespace: AtomicU64,
+    /// The number of redirects that have been skipped
+    #[clap(skip)]
+    skipped_redirects: AtomicU64,
+    /// The number of articles that have been skipped for being too large
+    #[clap(skip)]
+    skipped_too_large: AtomicU64,
+    /// The number of articles that have been skipped for being too small
+    #[clap(skip)]
+    skipped_too_small: AtomicU64,
+}
+impl ArticleFilter {
+    /// Check if the specified article should be extracted,
+    /// counting it as filtered if it should not.
+    ///
+    /// Skipped redirects (and articles of the wrong size) are counted separately.
+    pub fn accept(&self, article: &Article) -> bool {
+        if self.skip_redirects && article.is_redirect() {
+            self.skipped_redirects.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let size = article.body.html.len();
+        if self.max_html_bytes.is_some_and(|max| size > max) {
+            self.skipped_too_large.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        if self.min_html_bytes.is_some_and(|min| size < min) {
+            self.skipped_too_small.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let accepted = self.matches(article);
+        if !accepted {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+        }
+        accepted
+    }
+    fn matches(&self, article: &Article) -> bool {
+        if let Some(ref regex) = self.filter_title {
+            if !regex.is_match(&article.name) {
+                return false;
+            }
+        }
+        if !self.namespaces.is_empty() {
+            let namespace = match article.namespace {
+                Some(ref namespace) => namespace.identifier,
+                None => guess_namespace(&article.name),
+            };
+            if !self.namespaces.contains(&namespace) {
+                self.filtered_namespace.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+        true
+    }
+    /// The number of articles that have been filtered out
+    #[inline]
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+    /// Print a summary of the articles that have been filtered out
+    pub fn report(&self) {
+        let filtered_namespace = self.filtered_namespace.load(Ordering::Relaxed);
+        if filtered_namespace > 0 {
+            warn!(
+                "Filtered out {} articles outside namespaces {:?}",
+                filtered_namespace, self.namespaces
+            );
+        }
+        if self.filtered() > 0 {
+            info!("Filtered out {} articles", self.filtered());
+        }
+        let skipped_redirects = self.skipped_redirects.load(Ordering::Relaxed);
+        if skipped_redirects > 0 {
+            info!("Skipped {} redirects", skipped_redirects);
+        }
+        let skipped_too_large = self.skipped_too_large.load(Ordering::Relaxed);
+        if let Some(max) = self.max_html_bytes.filter(|_| skipped_too_large > 0) {
+            info!(
+                "Skipped {} articles larger than {} bytes",
+                skipped_too_large, max
+            );
+        }
+        let skipped_too_small = self.skipped_too_small.load(Ordering::Relaxed);
+        if let Some(min) = self.min_html_bytes.filter(|_| skipped_too_small > 0) {
+            info!(
+                "Skipped {} articles smaller than {} bytes",
+                skipped_too_small, min
+            );
+        }
+    }
+}
+
+/// The (English) title prefixes of the standard namespaces
+const NAMESPACE_PREFIXES: &[(&str, i64)] = &[
+    ("Media", -2),
+    ("Special", -1),
+    ("Talk", 1),
+    ("User", 2),
+    ("User talk", 3),
+    ("Wikipedia", 4),
+    ("Wikipedia talk", 5),
+    ("File", 6),
+    ("File talk", 7),
+    ("MediaWiki", 8),
+    ("MediaWiki talk", 9),
+    ("Template", 10),
+    ("Template talk", 11),
+    ("Help", 12),
+    ("Help talk", 13),
+    ("Category", 14),
+    ("Category talk", 15),
+    ("Portal", 100),
+    ("Portal talk", 101),
+    ("Draft", 118),
+    ("Draft talk", 119),
+    ("TimedText", 710),
+    ("TimedText talk", 711),
+    ("Module", 828),
+    ("Module talk", 829),
+];
+
+/// Guess the namespace of an article from the prefix of its title,
+/// for dumps without namespace information.
+///
+/// Titles without a known prefix are in the main namespace (0).
+fn guess_namespace(title: &str) -> i64 {
+    title
+        .split_once(':')
+        .and_then(|(prefix, _)| {
+            NAMESPACE_PREFIXES
+                .iter()
+                .find(|&&(known, _)| known == prefix)
+                .map(|&(_, id)| id)
+        })
+        .unwrap_or(0)
+}
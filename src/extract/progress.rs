@@ -0,0 +1,135 @@
+//! Reporting the progress of an extraction
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::info;
+
+/// Reports the progress of an extraction to stderr.
+///
+/// This is a live progress bar when stderr is a terminal,
+/// falling back to periodic lines (like [super::basic_report_progress]) otherwise.
+pub enum Progress {
+    Bar(ProgressBar),
+    Lines,
+}
+impl Progress {
+    /// Create a progress reporter.
+    ///
+    /// If `enabled` is false, this always uses the periodic lines.
+    /// The `limit` (if any) is used as the length of the progress bar.
+    pub fn new(enabled: bool, limit: Option<u64>) -> Progress {
+        if !enabled || !std::io::stderr().is_terminal() {
+            return Progress::Lines;
+        }
+        let bar = match limit {
+            Some(limit) => ProgressBar::new(limit).with_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] {wide_bar} {pos}/{len} articles ({per_sec}, ETA {eta}) {msg}",
+                )
+                .unwrap(),
+            ),
+            None => ProgressBar::new_spinner().with_style(
+                ProgressStyle::with_template(
+                    "{spinner} [{elapsed_precise}] {pos} articles ({per_sec}) {msg}",
+                )
+                .unwrap(),
+            ),
+        };
+        bar.enable_steady_tick(Duration::from_millis(200));
+        Progress::Bar(bar)
+    }
+    /// Report that the article with the specified count has been extracted
+    pub fn extracted(&self, count: u64, article_name: &str) {
+        match *self {
+            Progress::Bar(ref bar) => bar.inc(1),
+            Progress::Lines => super::basic_report_progress(count, article_name),
+        }
+    }
+    /// Report that an article has been skipped, `skipped` being the number skipped so far
+    pub fn skipped(&self, skipped: u64) {
+        match *self {
+            Progress::Bar(ref bar) => bar.set_message(format!("{} skipped", skipped + 1)),
+            Progress::Lines => {
+                if skipped.is_multiple_of(500) {
+                    info!("Skipped {} files", skipped);
+                }
+            }
+        }
+    }
+    /// Finish reporting progress, clearing any progress bar
+    pub fn finish(&self) {
+        if let Progress::Bar(ref bar) = *self {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// The interval between reports of [JsonProgress]
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reports the progress of an extraction as JSON lines, for consumption by other programs.
+///
+/// Each line is an object like `{"processed":12345,"inserted":12000,"skipped":10,"elapsed_secs":42.1}`.
+pub struct JsonProgress {
+    start: Instant,
+    /// The output and the time of the last report
+    out: Mutex<(File, Instant)>,
+}
+impl JsonProgress {
+    /// Report the progress to the specified (already open) file descriptor
+    pub fn open_fd(fd: u32) -> anyhow::Result<Self> {
+        let path = format!("/dev/fd/{}", fd);
+        let out = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .map_err(|e| anyhow!("Unable to open progress file descriptor {}: {}", fd, e))?;
+        let start = Instant::now();
+        Ok(JsonProgress {
+            start,
+            out: Mutex::new((out, start)),
+        })
+    }
+    /// Report the progress, unless it has already been reported recently
+    pub fn report(&self, processed: u64, inserted: u64, skipped: u64) -> std::io::Result<()> {
+        self.write(processed, inserted, skipped, false)
+    }
+    /// Report the final progress, regardless of when it was last reported
+    pub fn finish(&self, processed: u64, inserted: u64, skipped: u64) -> std::io::Result<()> {
+        self.write(processed, inserted, skipped, true)
+    }
+    fn write(
+        &self,
+        processed: u64,
+        inserted: u64,
+        skipped: u64,
+        force: bool,
+    ) -> std::io::Result<()> {
+        let mut lock = self.out.lock().unwrap();
+        let (ref mut out, ref mut last) = *lock;
+        if !force && last.elapsed() < JSON_PROGRESS_INTERVAL {
+            return Ok(());
+        }
+        *last = Instant::now();
+        let line = JsonProgressLine {
+            processed,
+            inserted,
+            skipped,
+            elapsed_secs: self.start.elapsed().as_secs_f64(),
+        };
+        serde_json::to_writer(&mut *out, &line)?;
+        out.write_all(b"\n")?;
+        out.flush()
+    }
+}
+
+#[derive(serde::Serialize)]
+struct JsonProgressLine {
+    processed: u64,
+    inserted: u64,
+    skipped: u64,
+    elapsed_secs: f64,
+}
@@ -0,0 +1,53 @@
+//! Uniform random sampling of the extracted articles
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Samples a fixed number of items uniformly at random from a stream of unknown length.
+///
+/// This uses reservoir sampling ("Algorithm R"), so it holds at most `capacity` items.
+/// Each new item replaces a random existing one with decreasing probability.
+pub struct Reservoir<T> {
+    items: Vec<T>,
+    capacity: usize,
+    /// The total number of items offered
+    seen: u64,
+    rng: StdRng,
+}
+impl<T> Reservoir<T> {
+    /// Create a reservoir, seeded from the OS unless a `seed` is given
+    pub fn new(capacity: usize, seed: Option<u64>) -> Self {
+        Reservoir {
+            items: Vec::with_capacity(capacity),
+            capacity,
+            seen: 0,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_os_rng(),
+            },
+        }
+    }
+    /// Offer an item to the sample, which may replace an existing item
+    pub fn offer(&mut self, item: T) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let index = self.rng.random_range(0..self.seen);
+            if index < self.capacity as u64 {
+                self.items[index as usize] = item;
+            }
+        }
+    }
+    /// The number of items in the sample
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    /// Take the sampled items
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
@@ -1,210 +1,1571 @@
 use anyhow::anyhow;
 use anyhow::Result;
-use clap::Args;
+use clap::{ArgEnum, Args};
 use crossbeam::channel::{Receiver, Sender};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
+use log::{debug, info, warn};
+use rusqlite::{ErrorCode, OptionalExtension, Transaction, TransactionBehavior};
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use indicatif::{HumanBytes, HumanDuration};
+
+use super::filter::ArticleFilter;
+use super::progress::{JsonProgress, Progress};
+use super::sample::Reservoir;
+use super::titles::TitleSet;
+use super::url::parse_url;
+use super::ArticleReader;
 use super::ExtractError;
 use super::ExtractState;
+use super::ExtractStats;
+use super::OnEmpty;
+use super::ParseErrorLog;
+use crate::cachedir::{ExtractionMeta, CACHEDIR_TAG};
+use crate::db::{self, NameKey, NO_COMPRESSION, ZSTD_COMPRESSION};
+use crate::html;
 
 const ARTICLE_CHANNEL_BOUND: usize = 50;
 
+/// The delay before the first retry of a busy commit, which doubles after each attempt
+const INITIAL_BUSY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// The writer has stopped receiving messages, because it failed.
+///
+/// The workers stop (without an error of their own) once they encounter this,
+/// so that the writer's error is reported.
 #[derive(Debug, thiserror::Error)]
-#[error("Cancelled extract")]
-struct CancelledError;
+#[error("SQL writer has stopped")]
+struct WriterStoppedError;
 
 #[derive(Debug, Args)]
 pub struct ExtractSqlCommand {
     /// The output database
     #[clap(long = "out", required = true, parse(from_os_str))]
     output: PathBuf,
-    #[clap(long = "workers", short = 'j', default_value = "4")]
-    workers: u32,
+    /// Split the output into N database shards, each with its own writer thread
+    ///
+    /// The shards are named after the output (`out-000.db`, `out-001.db`, etc),
+    /// and each article is routed by a stable hash of its name,
+    /// so the same title always lands in the same shard.
+    #[clap(long, default_value = "1", conflicts_with = "sample")]
+    shards: u32,
+    /// The number of threads parsing the targets (defaults to the number of CPUs)
+    #[clap(long = "workers", short = 'j')]
+    workers: Option<u32>,
+    /// The zstd compression level to store article bodies with (1-22)
+    #[clap(
+        long,
+        default_value = "1",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_compression_level)
+    )]
+    compression_level: i32,
+    /// Allow negative ("fast") compression levels
+    #[clap(long)]
+    allow_negative_level: bool,
+    /// Store article bodies as raw uncompressed UTF-8
+    #[clap(long, conflicts_with = "compression-level")]
+    no_compression: bool,
+    /// Compress articles with at least this many bytes of HTML using a streaming encoder
+    ///
+    /// Smaller articles reserve room for the worst-case compressed size up front,
+    /// which doubles the peak memory of very large articles.
+    /// The streaming encoder only grows its output as needed, but is a bit slower.
+    #[clap(long, default_value = "4194304")]
+    stream_compression_threshold: usize,
+    /// Build a full-text search index of the article text (`article_fts`)
+    ///
+    /// The index is an FTS5 table whose rowid is the `article.id`.
+    #[clap(long)]
+    fts: bool,
+    /// Store the visible plain text of each article (`article_body.text`)
+    ///
+    /// This parses the HTML, which is significantly slower.
+    #[clap(long)]
+    text: bool,
+    /// Store the internal links between articles (the `wikilink` table)
+    #[clap(long)]
+    wikilinks: bool,
+    /// Remove scripts, styles, edit links and navigation boxes from the stored HTML
+    ///
+    /// The cleaned HTML is also used for `--text`, `--fts` and `--wikilinks`.
+    /// Like those, this parses the HTML, which is significantly slower.
+    #[clap(long)]
+    clean_html: bool,
+    /// Only store the main content of each article (`<div class="mw-parser-output">`)
+    ///
+    /// Articles without the content element are stored in full.
+    #[clap(long)]
+    content_only: bool,
+    /// Store the text of the lead paragraph of each article (`article.summary`)
+    ///
+    /// Like `--text`, this parses the HTML, which is significantly slower.
+    #[clap(long)]
+    summary: bool,
+    /// Store the number of words in each article and an estimate of the time to read it
+    /// (`article.word_count` and `article.reading_seconds`)
+    ///
+    /// The words are counted in the plain text, assuming 200 words per minute.
+    /// Like `--text`, this parses the HTML, which is significantly slower.
+    #[clap(long)]
+    word_count: bool,
+    /// Create a case-insensitive index of the article names, for `query --ignore-case`
+    ///
+    /// Like SQLite's `NOCASE` collation, this only ignores the case of ASCII letters.
+    /// The names are still unique including their case,
+    /// so several articles can match a case-insensitive lookup.
+    #[clap(long)]
+    nocase_index: bool,
+    /// Deduplicate articles whose names only differ by case (like `Nice` and `NICE`)
+    ///
+    /// By default, only names which differ by their Unicode normalization form are duplicates.
+    /// This can only be chosen when creating the database, since the keys are unique.
+    /// Each dropped duplicate is logged.
+    #[clap(long)]
+    dedup_ignore_case: bool,
+    /// Update existing articles whose content has changed, instead of skipping them
+    ///
+    /// Changes are detected by the content hash (`article_body.content_sha256`),
+    /// and each update increments the `article_body.revision`.
+    #[clap(long)]
+    update: bool,
+    /// Skip target files which have already been completely extracted
+    /// into the database (with the same modification time)
+    ///
+    /// Every extraction records the files it has completed (even without `--resume`),
+    /// so an interrupted extraction can be resumed later.
+    /// Files are only recorded once they have been read to the end without any parse errors.
+    #[clap(long)]
+    resume: bool,
+    /// Delete any existing database (or shards) and start from scratch
+    ///
+    /// Without this, the articles are appended into an existing database.
+    #[clap(long, visible_alias = "force", conflicts_with_all = &["resume", "update"])]
+    fresh: bool,
+    /// Reset the checkpointed count of articles in the database
+    #[clap(long)]
+    reset_checkpoint: bool,
+    /// Don't display a progress bar, just print periodic progress lines
+    ///
+    /// This is the default when stderr isn't a terminal.
+    #[clap(long)]
+    no_progress: bool,
+    /// Periodically write the progress as JSON lines to a file descriptor (`--progress-fd`)
+    ///
+    /// Each line is an object like `{"processed":12345,"inserted":12000,"skipped":10,"elapsed_secs":42.1}`.
+    #[clap(long)]
+    progress_json: bool,
+    /// The (already open) file descriptor to write the JSON progress to
+    #[clap(long, default_value = "3", requires = "progress-json")]
+    progress_fd: u32,
+    /// Create the secondary indexes up front, instead of after loading the articles
+    ///
+    /// By default, the indexes are only deferred if the database doesn't have them yet
+    /// (a new database or an interrupted load).
+    #[clap(long)]
+    no_defer_indexes: bool,
+    /// The number of articles to insert per transaction
+    #[clap(long, default_value = "500")]
+    batch_size: u64,
+    /// How long SQLite waits for a lock held by another connection, in milliseconds
+    /// (`PRAGMA busy_timeout`)
+    #[clap(long, default_value = "5000")]
+    busy_timeout: u64,
+    /// The number of times to retry committing a batch while the database is busy
+    ///
+    /// This is on top of the `--busy-timeout`, with an exponential backoff between attempts.
+    #[clap(long, default_value = "5")]
+    busy_retries: u32,
+    /// Checkpoint the write-ahead log every N committed batches (0 to disable)
+    ///
+    /// Otherwise SQLite only checkpoints when it gets the chance,
+    /// so the `-wal` file can keep growing during a long extraction.
+    #[clap(long, default_value = "100")]
+    checkpoint_interval: u64,
+    /// How carefully SQLite syncs the database to disk (`PRAGMA synchronous`)
+    ///
+    /// With `normal`, a power loss or OS crash may roll back the most recent transactions,
+    /// but never corrupts the database. With `off`, such a crash can corrupt the database,
+    /// which is only worth it if it would be regenerated anyway.
+    /// With `full`, every transaction is durable once committed, at the cost of throughput.
+    /// A crash of the extractor itself is safe with any of these.
+    #[clap(long, arg_enum, default_value = "normal")]
+    synchronous: Synchronous,
+    /// The size of SQLite's page cache per connection (`PRAGMA cache_size`)
+    ///
+    /// A positive value is a number of pages, and a negative value is a number of KiB
+    /// (like SQLite itself). Defaults to SQLite's default of 2 MiB.
+    #[clap(long, allow_hyphen_values = true)]
+    cache_size: Option<i64>,
+    /// The number of bytes of the database to memory map (`PRAGMA mmap_size`)
+    ///
+    /// This can speed up reads of large databases,
+    /// but an I/O error then crashes the process instead of returning an error.
+    /// Defaults to SQLite's default (usually no memory mapping).
+    #[clap(long)]
+    mmap_size: Option<u64>,
+    /// Abort the extraction once this many records have failed to parse
+    #[clap(long)]
+    max_errors: Option<u64>,
+    /// Exit successfully even if some records failed to parse
+    #[clap(long)]
+    ignore_errors: bool,
+    /// What to do with articles whose HTML is empty (or missing)
+    ///
+    /// Either way, they are counted in the "empty body" total of the summary.
+    #[clap(long, arg_enum, default_value = "store")]
+    on_empty: OnEmpty,
+    /// Don't write a `CACHEDIR.TAG` and `extraction-meta.json` into the directory of the database
+    ///
+    /// The tag tells backup tools that the contents are regenerable.
+    /// It is only written into a directory created by the extraction (or already tagged),
+    /// so the database can be written into an existing directory without tagging it.
+    #[clap(long)]
+    no_cachedir_tag: bool,
+    /// Checkpoint, `VACUUM` and `ANALYZE` the database once extraction completes
+    #[clap(long)]
+    optimize: bool,
+    /// Skip the first N parsed articles (after filtering) before extracting any
+    ///
+    /// Articles are counted in the order they are parsed. With multiple target files
+    /// (or workers), the files are parsed concurrently and their articles interleave
+    /// nondeterministically, so only a single target gives a reproducible window.
+    /// Combine with `--limit` to extract a window of articles.
+    #[clap(long, visible_alias = "offset", default_value = "0")]
+    skip: u64,
     /// The limit on the number of articles to extract
+    ///
+    /// Exactly this many (new) articles are inserted into the database,
+    /// unless the targets run out first.
     #[clap(long = "limit")]
     limit: Option<u64>,
-    /// The target files to extract
+    /// Extract a uniformly random sample of N articles, instead of the first N
+    ///
+    /// The sampled articles (including their compressed bodies) are buffered in memory
+    /// until all the targets have been read, so memory use grows with N.
+    /// Duplicate articles in the sample are still skipped, so fewer may be inserted.
+    #[clap(long, conflicts_with = "limit")]
+    sample: Option<usize>,
+    /// The random seed used by `--sample`, for a reproducible sample
+    #[clap(long, requires = "sample")]
+    seed: Option<u64>,
+    /// Only extract the articles whose titles are listed in the file (one per line)
+    ///
+    /// Underscores and percent escapes are ignored when matching,
+    /// so `Foo_bar` matches `Foo bar`.
+    #[clap(long, parse(from_os_str))]
+    titles_file: Option<PathBuf>,
+    #[clap(flatten)]
+    filter: ArticleFilter,
+    /// The target files to extract (`-` reads from stdin)
+    ///
+    /// Directories and glob patterns are expanded into the input files they contain.
     #[clap(required = true, parse(from_os_str))]
     targets: Vec<PathBuf>,
 }
 
+/// How carefully SQLite syncs the database to disk (`PRAGMA synchronous`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Synchronous {
+    /// Never sync, leaving it to the OS
+    Off,
+    /// Sync at WAL checkpoints
+    Normal,
+    /// Sync after every transaction
+    Full,
+}
+impl Synchronous {
+    fn pragma_value(self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+        }
+    }
+}
+
+pub(crate) fn parse_compression_level(s: &str) -> Result<i32, String> {
+    let level: i32 = s.parse().map_err(|e| format!("{}", e))?;
+    let range = zstd::compression_level_range();
+    if level == 0 || !range.contains(&level) {
+        return Err(format!(
+            "Expected a compression level in {}..={} (excluding 0)",
+            range.start(),
+            range.end()
+        ));
+    }
+    Ok(level)
+}
+
+/// A message sent to a writer thread
+enum WriterMessage {
+    Article(Box<SqlArticleMessage>),
+    /// A source file has been completely extracted
+    FileDone(ProcessedFile),
+}
+
+/// A source file which has been completely extracted
+#[derive(Clone)]
+struct ProcessedFile {
+    /// The canonical path of the file
+    path: String,
+    /// The modification time of the file (in seconds since the epoch)
+    mtime: i64,
+    article_count: u64,
+}
+impl ProcessedFile {
+    /// Identify the specified source file by its canonical path and modification time.
+    ///
+    /// Returns `None` for stdin, which can never be resumed.
+    fn identify(target: &Path, article_count: u64) -> std::io::Result<Option<Self>> {
+        if super::is_stdin(target) {
+            return Ok(None);
+        }
+        let mtime = std::fs::metadata(target)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        Ok(Some(ProcessedFile {
+            path: std::fs::canonicalize(target)?
+                .to_string_lossy()
+                .into_owned(),
+            mtime,
+            article_count,
+        }))
+    }
+    /// Check if this file has already been recorded as processed
+    fn is_recorded(&self, conn: &rusqlite::Connection) -> rusqlite::Result<bool> {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM processed_file WHERE path = ?1 AND mtime = ?2)",
+            rusqlite::params![&self.path, &self.mtime],
+            |row| row.get(0),
+        )
+    }
+    fn record(&self, conn: &rusqlite::Connection) -> rusqlite::Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO processed_file(path, mtime, article_count) \
+            VALUES(?1, ?2, ?3)",
+            rusqlite::params![&self.path, &self.mtime, &self.article_count],
+        )?;
+        Ok(())
+    }
+}
+
 struct SqlArticleMessage {
     name: String,
     url: String,
+    page_id: Option<u64>,
+    namespace: Option<i64>,
+    /// The language code of the wiki (parsed from the url)
+    lang: Option<String>,
+    /// The text of the lead paragraph (if `--summary`)
+    summary: Option<String>,
+    /// The number of words in the text (if `--word-count`)
+    word_count: Option<u64>,
+    /// The normalized name, which articles are deduplicated by (see [NameKey])
+    name_key: String,
     count: u64,
+    /// The size of the original html (before compression)
+    raw_len: usize,
+    /// The SHA-256 hash of the original html
+    content_sha256: String,
     compressed_html: Vec<u8>,
+    /// The compression marker for `compressed_html`
+    compression: &'static str,
+    /// The plain text of the article (if needed for `--text` or `--fts`)
+    text: Option<String>,
+    /// The (deduplicated) titles of the internal links (if `--wikilinks`)
+    wikilinks: Vec<String>,
+}
+
+/// The words read per minute, used to estimate the reading time of an article
+const READING_WORDS_PER_MINUTE: u64 = 200;
+
+/// Estimate the time to read an article with the specified number of words (rounded up)
+fn reading_seconds(word_count: u64) -> u64 {
+    (word_count * 60).div_ceil(READING_WORDS_PER_MINUTE)
+}
+
+/// The shard of the article with the specified name key,
+/// so that duplicates always go to the same shard.
+///
+/// This uses the FNV-1a hash, which (unlike the standard library's hasher)
+/// is stable across runs and versions.
+fn shard_index(name: &str, shards: usize) -> usize {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+    let hash = name.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    });
+    (hash % shards as u64) as usize
+}
+
+thread_local! {
+    /// The zstd compressor of each worker thread (and its level),
+    /// reused across articles so its context is only allocated once.
+    static COMPRESSOR: RefCell<Option<(i32, zstd::bulk::Compressor<'static>)>> =
+        const { RefCell::new(None) };
+}
+
+/// Compress the HTML of an article, with the compressor of the current thread
+///
+/// The output is shrunk to fit, since the compressor reserves the worst-case size
+/// (see [zstd::zstd_safe::compress_bound]) and the message may wait in a channel.
+fn compress(html: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+    let mut compressed = COMPRESSOR.with(|compressor| {
+        let mut compressor = compressor.borrow_mut();
+        match *compressor {
+            Some((current, ref mut compressor)) if current == level => compressor.compress(html),
+            _ => {
+                let (_, compressor) =
+                    compressor.insert((level, zstd::bulk::Compressor::new(level)?));
+                compressor.compress(html)
+            }
+        }
+    })?;
+    compressed.shrink_to_fit();
+    Ok(compressed)
+}
+
+/// The size of the chunks fed to the streaming encoder
+const STREAM_COMPRESSION_CHUNK: usize = 1 << 20;
+
+/// Compress the HTML of a (large) article with a streaming encoder,
+/// which only grows the output as needed.
+fn compress_streaming(html: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+    let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), level)?;
+    encoder.set_pledged_src_size(Some(html.len() as u64))?;
+    for chunk in html.chunks(STREAM_COMPRESSION_CHUNK) {
+        encoder.write_all(chunk)?;
+    }
+    let mut compressed = encoder.finish()?;
+    compressed.shrink_to_fit();
+    Ok(compressed)
 }
 
+#[derive(Clone)]
 struct SqlMessageListener {
-    article_sender: Sender<SqlArticleMessage>,
-    limit: Option<u64>,
+    /// The senders to the writer of each shard
+    article_senders: Vec<Sender<WriterMessage>>,
+    /// How the names are normalized, which articles are deduplicated by
+    name_key: NameKey,
+    filter: Arc<ArticleFilter>,
+    /// The requested titles (if `--titles-file`)
+    titles: Option<Arc<TitleSet>>,
+    /// The zstd compression level, or `None` to store uncompressed
+    compression_level: Option<i32>,
+    /// The size of HTML which is compressed with [compress_streaming]
+    stream_compression_threshold: usize,
+    /// Whether to extract the plain text of the article
+    extract_text: bool,
+    /// Whether to extract the internal links of the article
+    extract_wikilinks: bool,
+    /// Whether to extract the lead paragraph of the article
+    extract_summary: bool,
+    /// Whether to count the words of the article
+    count_words: bool,
+    /// The sizes before and after cleaning the HTML (if `--clean-html`)
+    cleaned: Option<Arc<CleanTotals>>,
+    /// The number of articles missing their main content (if `--content-only`)
+    missing_content: Option<Arc<AtomicU64>>,
+    parse_errors: Arc<ParseErrorLog>,
+}
+
+/// The total size of the HTML before and after `--clean-html`
+#[derive(Default)]
+struct CleanTotals {
+    articles: AtomicU64,
+    before: AtomicU64,
+    after: AtomicU64,
 }
 
 impl super::ExtractListener for SqlMessageListener {
-    fn on_parse(&self, event: super::ParseEvent) -> Result<(), anyhow::Error> {
-        if let Some(limit) = self.limit {
-            if event.count > limit {
-                return Err(CancelledError.into());
+    fn accept(&self, article: &super::Article) -> bool {
+        self.filter.accept(article)
+            && self
+                .titles
+                .as_ref()
+                .is_none_or(|titles| titles.contains(&article.name))
+    }
+    fn on_parse(&self, mut event: super::ParseEvent) -> Result<(), anyhow::Error> {
+        let (mut text, mut wikilinks, mut summary, mut word_count) = (None, Vec::new(), None, None);
+        if self.extract_text
+            || self.count_words
+            || self.extract_wikilinks
+            || self.extract_summary
+            || self.cleaned.is_some()
+            || self.missing_content.is_some()
+        {
+            let mut document = html::parse(&event.article.body.html);
+            if let Some(ref cleaned) = self.cleaned {
+                html::clean(&mut document);
+                let html = document.html();
+                cleaned.articles.fetch_add(1, Ordering::Relaxed);
+                cleaned
+                    .before
+                    .fetch_add(event.article.body.html.len() as u64, Ordering::Relaxed);
+                cleaned
+                    .after
+                    .fetch_add(html.len() as u64, Ordering::Relaxed);
+                event.article.body.html = html;
+            }
+            let mut root = document.root_element();
+            if let Some(ref missing_content) = self.missing_content {
+                match html::content_root(&document) {
+                    Some(content) => {
+                        root = content;
+                        event.article.body.html = content.html();
+                    }
+                    None => {
+                        missing_content.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            if self.extract_text || self.count_words {
+                let extracted = html::extract_text(root);
+                if self.count_words {
+                    word_count = Some(extracted.split_whitespace().count() as u64);
+                }
+                if self.extract_text {
+                    text = Some(extracted);
+                }
+            }
+            if self.extract_wikilinks {
+                wikilinks = html::extract_wikilinks(root);
+            }
+            if self.extract_summary {
+                summary = html::extract_summary(html::content_root(&document).unwrap_or(root));
             }
         }
-        let raw_html = event.article.body.html.as_bytes();
-        let compressed = zstd::encode_all(raw_html, /* level */ 1)?;
-        self.article_sender
-            .send(SqlArticleMessage {
+        let raw_len = event.article.body.html.len();
+        let content_sha256 = db::content_sha256(&event.article.body.html);
+        let (compressed, compression) = match self.compression_level {
+            Some(level) if raw_len >= self.stream_compression_threshold => (
+                compress_streaming(event.article.body.html.as_bytes(), level)?,
+                ZSTD_COMPRESSION,
+            ),
+            Some(level) => (
+                compress(event.article.body.html.as_bytes(), level)?,
+                ZSTD_COMPRESSION,
+            ),
+            None => (event.article.body.html.into_bytes(), NO_COMPRESSION),
+        };
+        let lang = parse_url(&event.article.url).ok().and_then(|url| url.lang);
+        let name_key = self.name_key.key(&event.article.name);
+        let shard = shard_index(&name_key, self.article_senders.len());
+        self.article_senders[shard]
+            .send(WriterMessage::Article(Box::new(SqlArticleMessage {
                 name: event.article.name,
                 url: event.article.url,
+                page_id: event.article.identifier,
+                namespace: event.article.namespace.map(|ns| ns.identifier),
+                lang,
+                summary,
+                word_count,
+                name_key,
+                raw_len,
+                content_sha256,
                 compressed_html: compressed,
+                compression,
+                text,
+                wikilinks,
                 count: event.count,
-            })
-            .unwrap();
+            })))
+            .map_err(|_| WriterStoppedError)?;
         Ok(())
     }
 
     fn on_parse_error(
         &self,
-        _original_file: &std::path::Path,
+        original_file: &std::path::Path,
         cause: anyhow::Error,
     ) -> Result<(), anyhow::Error> {
-        eprintln!("ERROR: Unable to parse file: {}", cause);
+        self.parse_errors.log(original_file, &cause);
         Ok(())
     }
 }
+/// Options controlling what the writer stores for each article
+struct WriteOptions {
+    /// Store the text in `article_body.text`
+    store_text: bool,
+    /// Index the text in `article_fts`
+    fts: bool,
+    /// Store the internal links in `wikilink`
+    wikilinks: bool,
+    /// Update existing articles whose content has changed
+    update: bool,
+    /// How the names are normalized (only used to log duplicates)
+    name_key: NameKey,
+}
+/// The result of writing an article
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteOutcome {
+    /// The article was new
+    Inserted,
+    /// The article already existed with different content (only with `--update`)
+    Updated,
+    /// The article already existed (with the same content, if `--update`)
+    Skipped,
+}
+/// Write the article as part of the current batch.
+///
+/// If an article with the same name already exists, nothing is inserted.
+/// Names are compared by their [NameKey], so the name of the first article is kept.
+/// With `--update`, the existing article is updated instead if its content hash differs.
 fn serialize_article(
-    conn: &mut rusqlite::Connection,
+    tx: &rusqlite::Transaction,
+    options: &WriteOptions,
+    state: &ExtractState,
+    progress: &Progress,
     skipped: &AtomicU64,
     message: SqlArticleMessage,
-) -> Result<(), anyhow::Error> {
-    let tx = conn.transaction()?;
-    match tx.execute(
-        "INSERT INTO article(name, url) VALUES (?1, ?2);",
-        rusqlite::params![&message.name, &message.url],
-    ) {
-        Ok(_) => {}
-        Err(rusqlite::Error::SqliteFailure(cause, _))
-            if cause.code == rusqlite::ffi::ErrorCode::ConstraintViolation =>
-        {
-            let s = skipped.fetch_add(1, Ordering::SeqCst);
-            if s % 500 == 0 {
-                eprintln!("Skipped {} files", s);
+) -> Result<WriteOutcome, anyhow::Error> {
+    // NOTE: The statements are cached by the connection, so they are only prepared once
+    let article_id = tx
+        .prepare_cached(
+            "INSERT INTO article(name, url, page_id, namespace, lang, summary, word_count, \
+            reading_seconds, name_key) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) \
+            ON CONFLICT DO NOTHING RETURNING id",
+        )?
+        .query_row(
+            rusqlite::params![
+                &message.name,
+                &message.url,
+                &message.page_id,
+                &message.namespace,
+                &message.lang,
+                &message.summary,
+                &message.word_count,
+                message.word_count.map(reading_seconds),
+                &message.name_key
+            ],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?;
+    let outcome = match article_id {
+        Some(article_id) => {
+            insert_body(tx, options, article_id, &message)?;
+            insert_extras(tx, options, article_id, &message)?;
+            tx.prepare_cached(
+                "INSERT INTO meta(key, value) VALUES(?1, 1) \
+                ON CONFLICT(key) DO UPDATE SET value = value + 1",
+            )?
+            .execute([db::ARTICLE_COUNT_KEY])?;
+            WriteOutcome::Inserted
+        }
+        None if options.update && update_article(tx, options, &message)? => WriteOutcome::Updated,
+        None => {
+            if options.name_key == NameKey::IgnoreCase {
+                log_case_duplicate(tx, &message)?;
             }
-            // Article already exists, just ignore
-            return Ok(());
+            progress.skipped(skipped.fetch_add(1, Ordering::SeqCst));
+            // Article already exists (and hasn't changed), just ignore
+            return Ok(WriteOutcome::Skipped);
         }
-        Err(cause) => return Err(cause.into()),
+    };
+    state.add_bytes_out(message.compressed_html.len() as u64);
+    progress.extracted(message.count, &message.name);
+    Ok(outcome)
+}
+/// Log an article which is dropped as a duplicate of one whose name only differs by case
+fn log_case_duplicate(
+    tx: &rusqlite::Transaction,
+    message: &SqlArticleMessage,
+) -> rusqlite::Result<()> {
+    let existing = tx
+        .prepare_cached("SELECT name FROM article WHERE name_key = ?1")?
+        .query_row([&message.name_key], |row| row.get::<_, String>(0))
+        .optional()?;
+    // Names which only differ by their normalization are duplicates either way
+    let exact = |name: &str| NameKey::Exact.key(name);
+    if let Some(existing) = existing.filter(|existing| exact(existing) != exact(&message.name)) {
+        info!(
+            "Skipping {:?}, which only differs by case from {:?}",
+            message.name, existing
+        );
     }
-    let article_id = tx.last_insert_rowid();
-    if message.count % 500 == 0 {
-        let actual_article_id = tx.query_row(
-            "SELECT id FROM article WHERE name=?",
-            rusqlite::params![&message.name],
-            |row| row.get::<_, i64>(0),
-        )?;
-        assert_eq!(article_id, actual_article_id);
+    Ok(())
+}
+/// Update the existing article with the same name, if its content hash has changed.
+///
+/// This replaces the body (bumping its `revision`), full text index and links.
+/// An article without any body (like one merged from a partial database) is given one.
+/// Returns whether the article was updated.
+fn update_article(
+    tx: &rusqlite::Transaction,
+    options: &WriteOptions,
+    message: &SqlArticleMessage,
+) -> anyhow::Result<bool> {
+    let existing = tx
+        .prepare_cached(
+            "SELECT article.id, article_body.id IS NOT NULL, article_body.content_sha256 \
+            FROM article LEFT JOIN article_body ON article_body.article_id = article.id \
+            WHERE article.name_key = ?1",
+        )?
+        .query_row([&message.name_key], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, bool>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })
+        .optional()?;
+    let (article_id, has_body, old_sha256) = match existing {
+        Some(existing) => existing,
+        // The conflict wasn't on the name (so there is nothing to update)
+        None => return Ok(false),
+    };
+    if has_body && old_sha256.as_ref() == Some(&message.content_sha256) {
+        return Ok(false);
     }
-    tx.execute(
-        "INSERT INTO article_body(article_id, compressed_html) VALUES(?1, ?2)",
-        rusqlite::params![&article_id, &message.compressed_html],
-    )?;
-    tx.commit()?;
-    super::basic_report_progress(message.count, &message.name, false);
+    tx.prepare_cached(
+        "UPDATE article SET url = ?2, page_id = ?3, namespace = ?4, lang = ?5, summary = ?6, \
+        word_count = ?7, reading_seconds = ?8 WHERE id = ?1",
+    )?
+    .execute(rusqlite::params![
+        &article_id,
+        &message.url,
+        &message.page_id,
+        &message.namespace,
+        &message.lang,
+        &message.summary,
+        &message.word_count,
+        message.word_count.map(reading_seconds)
+    ])?;
+    if has_body {
+        tx.prepare_cached(
+            "UPDATE article_body SET compressed_html = ?2, compression = ?3, text = ?4, \
+            content_sha256 = ?5, revision = revision + 1 WHERE article_id = ?1",
+        )?
+        .execute(rusqlite::params![
+            &article_id,
+            &message.compressed_html,
+            message.compression,
+            message.text.as_ref().filter(|_| options.store_text),
+            &message.content_sha256,
+        ])?;
+    } else {
+        insert_body(tx, options, article_id, message)?;
+    }
+    if options.fts {
+        tx.prepare_cached("DELETE FROM article_fts WHERE rowid = ?1")?
+            .execute([&article_id])?;
+    }
+    if options.wikilinks {
+        tx.prepare_cached("DELETE FROM wikilink WHERE article_id = ?1")?
+            .execute([&article_id])?;
+    }
+    insert_extras(tx, options, article_id, message)?;
+    Ok(true)
+}
+/// Insert the body of a new article
+fn insert_body(
+    tx: &rusqlite::Transaction,
+    options: &WriteOptions,
+    article_id: i64,
+    message: &SqlArticleMessage,
+) -> rusqlite::Result<()> {
+    tx.prepare_cached(
+        "INSERT INTO article_body(article_id, compressed_html, compression, text, content_sha256) \
+        VALUES(?1, ?2, ?3, ?4, ?5)",
+    )?
+    .execute(rusqlite::params![
+        &article_id,
+        &message.compressed_html,
+        message.compression,
+        message.text.as_ref().filter(|_| options.store_text),
+        &message.content_sha256,
+    ])?;
     Ok(())
 }
+/// Insert the full text index and links of the article (if enabled)
+fn insert_extras(
+    tx: &rusqlite::Transaction,
+    options: &WriteOptions,
+    article_id: i64,
+    message: &SqlArticleMessage,
+) -> rusqlite::Result<()> {
+    if options.fts {
+        tx.prepare_cached("INSERT INTO article_fts(rowid, text) VALUES(?1, ?2)")?
+            .execute(rusqlite::params![
+                &article_id,
+                message.text.as_ref().unwrap()
+            ])?;
+    }
+    if !message.wikilinks.is_empty() {
+        let mut insert_link =
+            tx.prepare_cached("INSERT INTO wikilink(article_id, target_title) VALUES(?1, ?2)")?;
+        for target_title in &message.wikilinks {
+            insert_link.execute(rusqlite::params![&article_id, target_title])?;
+        }
+    }
+    Ok(())
+}
+/// Spawn a worker which extracts the targets it receives.
+///
+/// Each target which is completely extracted (without any parse errors)
+/// is recorded in the `processed_file` table, so it can be skipped by `--resume`.
 fn spawn_worker(
+    index: u32,
     state: Arc<ExtractState>,
-    article_sender: Sender<SqlArticleMessage>,
+    listener: SqlMessageListener,
     path_recev: Receiver<PathBuf>,
+) -> std::io::Result<JoinHandle<anyhow::Result<()>>> {
+    std::thread::Builder::new()
+        .name(format!("extract-worker-{}", index))
+        .spawn(move || {
+            while let Ok(target) = path_recev.recv() {
+                info!("Processing {}", super::target_name(&target));
+                let reader = ArticleReader::open(&target)?;
+                // Identify the file before extraction, in case it is modified in the meantime
+                let processed =
+                    ProcessedFile::identify(&target, 0).map_err(|cause| ExtractError::FileIo {
+                        target: target.clone(),
+                        cause,
+                    })?;
+                match state.extract_reader(&target, reader, &listener) {
+                    Ok(outcome) if outcome.is_clean() && !state.is_stopped() => {
+                        if let Some(processed) = processed {
+                            let processed = ProcessedFile {
+                                article_count: outcome.extracted,
+                                ..processed
+                            };
+                            // Every shard records the file, after the articles it was sent
+                            for sender in &listener.article_senders {
+                                let message = WriterMessage::FileDone(processed.clone());
+                                if sender.send(message).is_err() {
+                                    return Ok(()); // the writer has stopped
+                                }
+                            }
+                        }
+                    }
+                    Ok(outcome) => {
+                        // Stopped before the file was finished, or some records were invalid
+                        if processed.is_some() && outcome.errors > 0 {
+                            warn!(
+                                "Not recording {} as processed, since {} records failed to parse",
+                                super::target_name(&target),
+                                outcome.errors
+                            );
+                        }
+                    }
+                    Err(ExtractError::Listener(cause)) if cause.is::<WriterStoppedError>() => break,
+                    Err(cause) => return Err(cause.into()),
+                }
+            }
+            Ok(())
+        })
+}
+/// The paths of the database shards written by the extraction.
+///
+/// With more than one shard, the shards are named after the output
+/// (so `out.db` is split into `out-000.db`, `out-001.db`, etc).
+fn shard_paths(output: &Path, shards: u32) -> Vec<PathBuf> {
+    if shards <= 1 {
+        return vec![output.to_path_buf()];
+    }
+    let stem = output
+        .file_stem()
+        .map_or_else(|| "out".into(), |stem| stem.to_string_lossy());
+    let extension = output
+        .extension()
+        .map_or_else(|| "db".into(), |ext| ext.to_string_lossy());
+    (0..shards)
+        .map(|index| output.with_file_name(format!("{}-{:03}.{}", stem, index, extension)))
+        .collect()
+}
+
+/// Open (or create) the database of a single shard,
+/// returning whether its secondary indexes should be deferred.
+fn open_shard(
+    path: &Path,
+    command: &ExtractSqlCommand,
+    targets: &[PathBuf],
+) -> anyhow::Result<(rusqlite::Connection, bool)> {
+    if command.fresh && path.is_file() {
+        db::remove_database(path)
+            .map_err(|e| anyhow!("Failed to remove database {}: {}", path.display(), e))?;
+        info!("Removed existing database {}", path.display());
+    }
+    let existing = path.is_file();
+    let connection = db::open_or_create(path)?;
+    let mut pragmas = format!(
+        "PRAGMA synchronous = {}; PRAGMA busy_timeout = {};",
+        command.synchronous.pragma_value(),
+        command.busy_timeout
+    );
+    if let Some(cache_size) = command.cache_size {
+        pragmas.push_str(&format!("PRAGMA cache_size = {};", cache_size));
+    }
+    if let Some(mmap_size) = command.mmap_size {
+        pragmas.push_str(&format!("PRAGMA mmap_size = {};", mmap_size));
+    }
+    connection.execute_batch(&pragmas)?;
+    if existing {
+        let count: u64 =
+            connection.query_row("SELECT COUNT(*) FROM article", [], |row| row.get(0))?;
+        info!(
+            "Appending into existing database {} with {} articles (use --fresh to replace it)",
+            path.display(),
+            count
+        );
+    }
+    let defer_indexes = !command.no_defer_indexes && !db::has_indexes(&connection)?;
+    if !defer_indexes {
+        db::create_indexes(&connection)?;
+    }
+    if command.fts {
+        db::create_fts_table(&connection)?;
+    }
+    if command.wikilinks {
+        db::create_wikilink_table(&connection)?;
+    }
+    if command.nocase_index {
+        db::create_nocase_index(&connection)?;
+    }
+    if command.reset_checkpoint {
+        connection.execute("DELETE FROM meta WHERE key = ?1", [db::ARTICLE_COUNT_KEY])?;
+    } else if let Some(count) = db::get_meta::<i64>(&connection, db::ARTICLE_COUNT_KEY)? {
+        info!("Resuming {} from {} articles", path.display(), count);
+    }
+    connection.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS processed_file(
+            path TEXT PRIMARY KEY,
+            mtime INTEGER NOT NULL,
+            article_count INTEGER NOT NULL
+        );
+    ",
+    )?;
+    record_provenance(&connection, path, command, targets)?;
+    Ok((connection, defer_indexes))
+}
+
+/// Record how the database was made in the `meta` table,
+/// warning if the compression level differs from when it was created.
+fn record_provenance(
+    connection: &rusqlite::Connection,
+    path: &Path,
+    command: &ExtractSqlCommand,
+    targets: &[PathBuf],
+) -> anyhow::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let level = if command.no_compression {
+        db::NO_COMPRESSION.to_string()
+    } else {
+        command.compression_level.to_string()
+    };
+    match db::get_meta::<String>(connection, db::COMPRESSION_LEVEL_KEY)? {
+        Some(existing) if existing != level => warn!(
+            "{} was created with compression level {}, but this extraction uses {}",
+            path.display(),
+            existing,
+            level
+        ),
+        Some(_) => {}
+        None => db::set_meta(connection, db::COMPRESSION_LEVEL_KEY, &level)?,
+    }
+    if db::get_meta::<i64>(connection, db::CREATED_AT_KEY)?.is_none() {
+        db::set_meta(connection, db::CREATED_AT_KEY, now)?;
+        db::set_meta(connection, db::CRATE_VERSION_KEY, env!("CARGO_PKG_VERSION"))?;
+    }
+    db::set_meta(connection, db::EXTRACTED_AT_KEY, now)?;
+    // The sources of every extraction, in the order they were first extracted
+    let mut sources = match db::get_meta::<String>(connection, db::SOURCES_KEY)? {
+        Some(json) => serde_json::from_str::<Vec<String>>(&json)?,
+        None => Vec::new(),
+    };
+    for target in targets {
+        let name = super::target_name(target).into_owned();
+        if !sources.contains(&name) {
+            sources.push(name);
+        }
+    }
+    db::set_meta(
+        connection,
+        db::SOURCES_KEY,
+        serde_json::to_string(&sources)?,
+    )?;
+    Ok(())
+}
+
+/// The state shared by all the writer threads
+struct WriterContext<'a> {
+    options: WriteOptions,
+    state: &'a ExtractState,
+    progress: &'a Progress,
+    json_progress: Option<&'a JsonProgress>,
+    skipped: &'a AtomicU64,
+    /// The number of articles inserted by all the writers
+    inserted: &'a AtomicU64,
+    /// The number of articles inserted (or about to be inserted) by all the writers
+    reserved: &'a AtomicU64,
     limit: Option<u64>,
-) -> JoinHandle<anyhow::Result<()>> {
-    std::thread::spawn(move || {
-        let listener = SqlMessageListener {
-            article_sender,
-            limit,
+    batch_size: u64,
+    checkpoint_interval: u64,
+    busy_retries: u32,
+}
+
+/// The totals counted by a single writer
+#[derive(Default)]
+struct WriterTotals {
+    inserted: u64,
+    /// The number of existing articles which were updated (with `--update`)
+    updated: u64,
+    /// The size of the original html
+    raw: u64,
+    /// The size of the (possibly) compressed html
+    compressed: u64,
+}
+
+/// Reserve a slot for inserting an article, returning false once the limit has been inserted.
+///
+/// While the limit is only reached by reserved slots, this waits for the other writers
+/// to either insert their articles or give back their slots.
+fn reserve_slot(ctx: &WriterContext) -> bool {
+    loop {
+        let slot = ctx.reserved.fetch_add(1, Ordering::SeqCst);
+        let limit = match ctx.limit {
+            Some(limit) if slot >= limit => limit,
+            _ => return true,
         };
-        while let Ok(target) = path_recev.recv() {
-            eprintln!("Processing {}", target.display());
-            match state.run_extract(target, &listener) {
-                Ok(()) => {}
-                Err(ExtractError::Listener(cause)) if cause.is::<CancelledError>() => {} // ignore
-                Err(cause) => return Err(cause.into()),
+        ctx.reserved.fetch_sub(1, Ordering::SeqCst);
+        if ctx.inserted.load(Ordering::SeqCst) >= limit {
+            return false;
+        }
+        std::thread::yield_now();
+    }
+}
+
+/// Write the articles received from the workers into the database of a shard,
+/// until all the workers have finished.
+///
+/// Each batch is only started once there is something to write,
+/// so the write lock isn't held while waiting on the workers (like a slow `stdin`).
+fn run_writer(
+    connection: &mut rusqlite::Connection,
+    article_recev: Receiver<WriterMessage>,
+    ctx: &WriterContext,
+    mut reservoir: Option<Reservoir<SqlArticleMessage>>,
+) -> anyhow::Result<WriterTotals> {
+    let mut totals = WriterTotals::default();
+    let mut batch = None;
+    let mut batch_len = 0u64;
+    let mut committed_batches = 0u64;
+    while let Ok(message) = article_recev.recv() {
+        match message {
+            WriterMessage::Article(article) if reservoir.is_some() => {
+                reservoir.as_mut().unwrap().offer(*article);
+            }
+            WriterMessage::Article(article) => {
+                /*
+                 * The limit is enforced here in the writers, since they are the only ones
+                 * who know how many articles were actually inserted.
+                 *
+                 * Previously each worker compared its own `event.count` against the limit,
+                 * so several workers could cross it at once and insert a few extra articles.
+                 * A slot is reserved before inserting, so that the writers of different shards
+                 * never exceed the limit between them.
+                 * The slot of a duplicate is given back, so while the other writers still
+                 * have reserved slots, this waits to see if one of them becomes free.
+                 * Only once the limit has actually been inserted is the article dropped.
+                 */
+                if !reserve_slot(ctx) {
+                    ctx.state.discard();
+                    // Drain the remaining articles, so the workers don't block
+                    continue;
+                }
+                let tx = match batch {
+                    Some(ref tx) => tx,
+                    None => batch.insert(begin_batch(connection, ctx.busy_retries)?),
+                };
+                totals.raw += article.raw_len as u64;
+                totals.compressed += article.compressed_html.len() as u64;
+                let outcome = serialize_article(
+                    tx,
+                    &ctx.options,
+                    ctx.state,
+                    ctx.progress,
+                    ctx.skipped,
+                    *article,
+                )
+                .inspect_err(|_| {
+                    // Don't leave the other writers waiting for the slot
+                    ctx.reserved.fetch_sub(1, Ordering::SeqCst);
+                })?;
+                if outcome == WriteOutcome::Inserted {
+                    totals.inserted += 1;
+                    let inserted = ctx.inserted.fetch_add(1, Ordering::SeqCst) + 1;
+                    if ctx.limit == Some(inserted) {
+                        ctx.state.stop();
+                    }
+                } else {
+                    // Only new articles count towards the limit
+                    ctx.reserved.fetch_sub(1, Ordering::SeqCst);
+                    totals.updated += (outcome == WriteOutcome::Updated) as u64;
+                }
+                batch_len += 1;
+            }
+            // All the articles of the file have already been sent to the writer
+            WriterMessage::FileDone(processed) => {
+                let tx = match batch {
+                    Some(ref tx) => tx,
+                    None => batch.insert(begin_batch(connection, ctx.busy_retries)?),
+                };
+                processed.record(tx)?;
             }
         }
-        Ok(())
+        if let Some(json_progress) = ctx.json_progress {
+            json_progress.report(
+                ctx.state.processed(),
+                ctx.inserted.load(Ordering::SeqCst),
+                ctx.skipped.load(Ordering::SeqCst),
+            )?;
+        }
+        if batch.is_some() && batch_len >= ctx.batch_size {
+            commit_batch(batch.take().unwrap(), ctx.busy_retries)?;
+            batch_len = 0;
+            committed_batches += 1;
+            // Never a multiple of an interval of 0, which disables checkpoints
+            if committed_batches.is_multiple_of(ctx.checkpoint_interval) {
+                checkpoint(connection)?;
+            }
+        }
+    }
+    if let Some(reservoir) = reservoir {
+        info!("Writing a sample of {} articles", reservoir.len());
+        let tx = match batch {
+            Some(ref tx) => tx,
+            None => batch.insert(begin_batch(connection, ctx.busy_retries)?),
+        };
+        for article in reservoir.into_items() {
+            totals.raw += article.raw_len as u64;
+            totals.compressed += article.compressed_html.len() as u64;
+            match serialize_article(
+                tx,
+                &ctx.options,
+                ctx.state,
+                ctx.progress,
+                ctx.skipped,
+                article,
+            )? {
+                WriteOutcome::Inserted => totals.inserted += 1,
+                WriteOutcome::Updated => totals.updated += 1,
+                WriteOutcome::Skipped => {}
+            }
+        }
+    }
+    /*
+     * Flush the final partial batch.
+     *
+     * If we were interrupted, the workers have already stopped sending articles
+     * and the channel has been drained.
+     */
+    if let Some(batch) = batch {
+        commit_batch(batch, ctx.busy_retries)?;
+    }
+    Ok(totals)
+}
+
+/// Start a batch, immediately taking the write lock on the database.
+///
+/// Taking the lock up front means the inserts themselves never find the database busy,
+/// which is only retried here (see [retry_busy]).
+fn begin_batch(
+    connection: &rusqlite::Connection,
+    retries: u32,
+) -> rusqlite::Result<Transaction<'_>> {
+    retry_busy("start a batch", retries, || {
+        Transaction::new_unchecked(connection, TransactionBehavior::Immediate)
     })
 }
-pub fn extract(command: ExtractSqlCommand) -> anyhow::Result<()> {
-    let target = command.output.clone();
-    if !target.is_file() {
-        let connection = rusqlite::Connection::open_with_flags(
-            target.clone(),
-            rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE | rusqlite::OpenFlags::SQLITE_OPEN_CREATE,
-        )?;
-        connection.execute_batch(
-            "
-            PRAGMA foreign_keys = ON;
-            CREATE TABLE article(
-                id INTEGER PRIMARY KEY,
-                name VARCHAR(255) UNIQUE NOT NULL,
-                url VARCHAR(255) NOT NULL
-            );
-            CREATE TABLE article_body(
-                id INTEGER PRIMARY KEY,
-                article_id INTEGER NOT NULL,
-                compressed_html BLOB,
-                FOREIGN KEY(article_id) REFERENCES article(id)
+
+/// Commit the batch, retrying while the database is busy (see [retry_busy]).
+///
+/// A failed `COMMIT` leaves the transaction active, so it can simply be repeated.
+/// The batch is rolled back if it still fails after all the retries.
+fn commit_batch(batch: Transaction, retries: u32) -> rusqlite::Result<()> {
+    // Dropping the committed transaction does nothing
+    retry_busy("commit a batch", retries, || batch.execute_batch("COMMIT"))
+}
+
+/// Retry the operation with an exponential backoff, while the database is busy or locked
+/// (after SQLite's own `busy_timeout` has already expired).
+fn retry_busy<T>(
+    action: &str,
+    retries: u32,
+    mut op: impl FnMut() -> rusqlite::Result<T>,
+) -> rusqlite::Result<T> {
+    let mut backoff = INITIAL_BUSY_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+                    && attempt < retries =>
+            {
+                attempt += 1;
+                warn!(
+                    "Database is busy, retrying to {} in {:?} (attempt {} of {})",
+                    action, backoff, attempt, retries
+                );
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Checkpoint the write-ahead log, without waiting on any readers (`PASSIVE`)
+fn checkpoint(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let (busy, wal_frames, checkpointed): (bool, i64, i64) =
+        connection.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+    debug!(
+        "Checkpointed {} of {} WAL frames{}",
+        checkpointed,
+        wal_frames,
+        if busy { " (busy)" } else { "" }
+    );
+    Ok(())
+}
+
+/// Extract the articles into the database, returning the statistics of the extraction
+pub fn extract(command: ExtractSqlCommand) -> anyhow::Result<ExtractStats> {
+    let start = Instant::now();
+    if command.compression_level < 0 && !command.allow_negative_level {
+        anyhow::bail!(
+            "Negative compression level {} requires --allow-negative-level",
+            command.compression_level
+        );
+    }
+    let targets = super::discover_targets(&command.targets)?;
+    let mut tagged_dir = None;
+    if let Some(parent) = command.output.parent() {
+        // NOTE: The parent of a bare file name is empty
+        if !parent.as_os_str().is_empty() {
+            if !parent.exists() || parent.join(CACHEDIR_TAG).is_file() {
+                tagged_dir = Some(parent.to_path_buf());
+            }
+            std::fs::create_dir_all(parent).map_err(|e| {
+                anyhow!(
+                    "Failed to create output directory {}: {}",
+                    parent.display(),
+                    e
+                )
+            })?;
+        }
+    }
+    let shard_paths = shard_paths(&command.output, command.shards);
+    let mut shards = Vec::with_capacity(shard_paths.len());
+    for path in &shard_paths {
+        shards.push(open_shard(path, &command, &targets)?);
+    }
+    let requested_key = if command.dedup_ignore_case {
+        NameKey::IgnoreCase
+    } else {
+        NameKey::Exact
+    };
+    let mut name_key = requested_key;
+    for (index, (path, (connection, _))) in shard_paths.iter().zip(&shards).enumerate() {
+        let shard_key = NameKey::init(connection, path, requested_key)?;
+        if index > 0 && shard_key != name_key {
+            anyhow::bail!(
+                "The shards of {} normalize their names differently (use --fresh to replace them)",
+                command.output.display()
             );
-            CREATE INDEX article_idx_url ON article(url);
-            CREATE INDEX article_body_idx_article_id ON article_body(article_id);
-        ",
-        )?;
-        connection.close().map_err(|(_, err)| err)?;
+        }
+        name_key = shard_key;
     }
-    let mut connection = rusqlite::Connection::open_with_flags(
-        &target,
-        rusqlite::OpenFlags::SQLITE_OPEN_READ_WRITE,
-    )?;
-    connection.execute_batch(
-        "
-        PRAGMA foreign_keys = ON;
-        PRAGMA journal_mode = WAL;
-    ",
-    )?;
-    let (article_sender, article_recev) = crossbeam::channel::bounded(ARTICLE_CHANNEL_BOUND);
+    let compression_level = if command.no_compression {
+        None
+    } else {
+        Some(command.compression_level)
+    };
+    let (article_senders, article_recevs): (Vec<_>, Vec<_>) = shards
+        .iter()
+        .map(|_| crossbeam::channel::bounded(ARTICLE_CHANNEL_BOUND))
+        .unzip();
     let (path_sender, path_recev) = crossbeam::channel::unbounded();
-    let state = Arc::new(ExtractState::new());
-    assert!(command.workers > 0);
+    let mut state = ExtractState::with_skip(command.skip).with_on_empty(command.on_empty);
+    if let Some(max_errors) = command.max_errors {
+        state = state.with_max_errors(max_errors);
+    }
+    let state = Arc::new(state);
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let state = Arc::clone(&state);
+        let interrupted = Arc::clone(&interrupted);
+        let res = ctrlc::set_handler(move || {
+            if interrupted.swap(true, Ordering::SeqCst) {
+                warn!("Interrupted again, exiting immediately");
+                std::process::exit(130);
+            }
+            warn!("Interrupted, finishing the current batch (press Ctrl-C again to force exit)");
+            state.stop();
+        });
+        match res {
+            // Only the first extraction in the process can be interrupted (like in the benchmarks)
+            Err(ctrlc::Error::MultipleHandlers) => debug!("Interrupt handler already installed"),
+            res => res?,
+        }
+    }
+    let titles = match command.titles_file {
+        Some(ref path) => {
+            let titles = TitleSet::load(path)?;
+            info!("Loaded {} requested titles", titles.len());
+            Some(Arc::new(titles))
+        }
+        None => None,
+    };
+    let filter = Arc::new(command.filter);
+    let cleaned = command.clean_html.then(|| Arc::new(CleanTotals::default()));
+    let missing_content = command.content_only.then(|| Arc::new(AtomicU64::new(0)));
+    let listener = SqlMessageListener {
+        article_senders,
+        name_key,
+        filter: Arc::clone(&filter),
+        titles: titles.clone(),
+        compression_level,
+        stream_compression_threshold: command.stream_compression_threshold,
+        extract_text: command.fts || command.text,
+        extract_wikilinks: command.wikilinks,
+        extract_summary: command.summary,
+        count_words: command.word_count,
+        cleaned: cleaned.clone(),
+        missing_content: missing_content.clone(),
+        parse_errors: Arc::new(ParseErrorLog::default()),
+    };
+    let workers = command.workers.unwrap_or_else(crate::default_workers);
+    if workers == 0 {
+        anyhow::bail!("Must have at least one worker");
+    }
+    info!("Using {} workers", workers);
     let mut handles = Vec::new();
-    for _ in 0..command.workers {
+    for index in 0..workers {
         handles.push(spawn_worker(
+            index,
             Arc::clone(&state),
-            article_sender.clone(),
+            listener.clone(),
             path_recev.clone(),
-            command.limit.clone(),
-        ))
+        )?)
     }
-    drop(article_sender);
+    drop(listener);
     drop(path_recev);
-    for target in &command.targets {
-        path_sender.send(target.clone()).unwrap();
+    let mut target_count = 0usize;
+    'targets: for target in &targets {
+        if command.resume {
+            let processed =
+                ProcessedFile::identify(target, 0).map_err(|cause| ExtractError::FileIo {
+                    target: target.clone(),
+                    cause,
+                })?;
+            if let Some(processed) = processed {
+                // The file is only complete once every shard has recorded it
+                let mut recorded = true;
+                for (connection, _) in &shards {
+                    recorded &= processed.is_recorded(connection)?;
+                }
+                if recorded {
+                    info!("Skipping already processed {}", target.display());
+                    continue 'targets;
+                }
+            }
+        }
+        if path_sender.send(target.clone()).is_err() {
+            break; // all the workers have failed (reported when they're joined)
+        }
+        target_count += 1;
     }
     drop(path_sender);
-    eprintln!("Extracted {} files", state.count());
+    let progress = Progress::new(
+        !command.no_progress,
+        command.limit.or(command.sample.map(|n| n as u64)),
+    );
+    let json_progress = if command.progress_json {
+        Some(JsonProgress::open_fd(command.progress_fd)?)
+    } else {
+        None
+    };
     let skipped = AtomicU64::new(0);
-    while let Ok(article) = article_recev.recv() {
-        serialize_article(&mut connection, &skipped, article)?;
+    let inserted = AtomicU64::new(0);
+    let reserved = AtomicU64::new(0);
+    let ctx = WriterContext {
+        options: WriteOptions {
+            store_text: command.text,
+            fts: command.fts,
+            wikilinks: command.wikilinks,
+            update: command.update,
+            name_key,
+        },
+        state: &state,
+        progress: &progress,
+        json_progress: json_progress.as_ref(),
+        skipped: &skipped,
+        inserted: &inserted,
+        reserved: &reserved,
+        limit: command.limit,
+        batch_size: command.batch_size,
+        checkpoint_interval: command.checkpoint_interval,
+        busy_retries: command.busy_retries,
+    };
+    let interrupted_ref = &interrupted;
+    let results = std::thread::scope(|scope| {
+        let writers = shards
+            .into_iter()
+            .zip(article_recevs)
+            .enumerate()
+            .map(
+                |(shard, ((mut connection, defer_indexes), article_recev))| {
+                    let ctx = &ctx;
+                    let reservoir = command
+                        .sample
+                        .map(|size| Reservoir::new(size, command.seed));
+                    std::thread::Builder::new()
+                        .name(format!("extract-writer-{}", shard))
+                        .spawn_scoped(scope, move || -> anyhow::Result<WriterTotals> {
+                            let totals =
+                                run_writer(&mut connection, article_recev, ctx, reservoir)?;
+                            if interrupted_ref.load(Ordering::SeqCst) {
+                                // Leave a clean database, without relying on WAL recovery
+                                connection.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+                            } else if defer_indexes {
+                                info!("Creating indexes");
+                                db::create_indexes(&connection)?;
+                            }
+                            connection.close().map_err(|(_, e)| e)?;
+                            Ok(totals)
+                        })
+                },
+            )
+            .collect::<std::io::Result<Vec<_>>>()?;
+        writers
+            .into_iter()
+            .map(|writer| {
+                writer
+                    .join()
+                    .map_err(|_| anyhow!("Unexpected panic in writer thread"))?
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+    });
+    progress.finish();
+    let shard_totals = results?;
+    if let Some(ref json_progress) = json_progress {
+        json_progress.finish(
+            state.processed(),
+            inserted.load(Ordering::SeqCst),
+            skipped.load(Ordering::SeqCst),
+        )?;
     }
-    connection.close().map_err(|(_, e)| e)?;
     for worker in handles {
         worker
             .join()
             .map_err(|_| anyhow!("Unexpected panic in worker thread"))??;
     }
-    eprintln!(
-        "Extracted {} articles from {} different source files",
+    let inserted = shard_totals
+        .iter()
+        .map(|totals| totals.inserted)
+        .sum::<u64>();
+    let total_raw = shard_totals.iter().map(|totals| totals.raw).sum::<u64>();
+    let total_compressed = shard_totals
+        .iter()
+        .map(|totals| totals.compressed)
+        .sum::<u64>();
+    if shard_paths.len() > 1 {
+        for (path, totals) in shard_paths.iter().zip(&shard_totals) {
+            info!("Wrote {} articles to {}", totals.inserted, path.display());
+        }
+    }
+    // NOTE: The parsed count includes duplicates and articles past the limit
+    info!(
+        "Extracted {} articles from {} different source files ({} parsed, {} duplicates skipped)",
+        inserted,
+        target_count,
         state.count(),
-        command.targets.len()
+        skipped.load(Ordering::SeqCst)
     );
-    Ok(())
+    if command.update {
+        let updated = shard_totals
+            .iter()
+            .map(|totals| totals.updated)
+            .sum::<u64>();
+        info!(
+            "Inserted {} new articles, updated {} changed articles, {} unchanged",
+            inserted,
+            updated,
+            skipped.load(Ordering::SeqCst)
+        );
+    }
+    let elapsed = start.elapsed();
+    info!(
+        "Read {} of HTML and wrote {} in {} ({:.1} articles/sec)",
+        HumanBytes(state.bytes_in()),
+        HumanBytes(state.bytes_out()),
+        HumanDuration(elapsed),
+        inserted as f64 / elapsed.as_secs_f64()
+    );
+    filter.report();
+    if let Some(ref titles) = titles {
+        titles.report();
+    }
+    if let Some(ref cleaned) = cleaned {
+        let articles = cleaned.articles.load(Ordering::Relaxed);
+        let before = cleaned.before.load(Ordering::Relaxed);
+        let after = cleaned.after.load(Ordering::Relaxed);
+        if articles > 0 && before > 0 {
+            info!(
+                "Cleaning the HTML removed {:.1}% on average ({} per article)",
+                (before - after.min(before)) as f64 * 100.0 / before as f64,
+                HumanBytes((before - after.min(before)) / articles)
+            );
+        }
+    }
+    if let Some(ref missing_content) = missing_content {
+        let missing = missing_content.load(Ordering::Relaxed);
+        if missing > 0 {
+            warn!(
+                "Stored the full HTML of {} articles without a main content element",
+                missing
+            );
+        }
+    }
+    if total_compressed > 0 && !command.no_compression {
+        info!(
+            "Average compression ratio {:.2} (level {})",
+            total_raw as f64 / total_compressed as f64,
+            command.compression_level
+        );
+    }
+    let stats = ExtractStats {
+        skipped: skipped.load(Ordering::SeqCst),
+        ..state.stats()
+    };
+    stats.log_summary();
+    if let Some(dir) = tagged_dir.filter(|_| !command.no_cachedir_tag) {
+        crate::cachedir::mark_output_dir(&dir, &ExtractionMeta::new(&targets, inserted))?;
+    }
+    if interrupted.load(Ordering::SeqCst) {
+        anyhow::bail!("Interrupted, use --resume to continue the extraction");
+    }
+    if stats.errors > 0 && !command.ignore_errors {
+        anyhow::bail!(
+            "Failed to parse {} records (use --ignore-errors to allow this)",
+            stats.errors
+        );
+    }
+    if command.optimize {
+        for path in &shard_paths {
+            crate::optimize::optimize(path)?;
+        }
+    }
+    Ok(stats)
 }
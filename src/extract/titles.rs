@@ -0,0 +1,88 @@
+//! Restricting extraction to a list of article titles
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use log::info;
+
+/// The maximum number of missing titles to list in the report
+const MAX_REPORTED_MISSING: usize = 20;
+
+/// A set of requested article titles, loaded from a file with one title per line.
+///
+/// Titles are compared after normalization (see [normalize_title]),
+/// so `Foo_bar`, `Foo%20bar` and `Foo bar` all match each other.
+pub struct TitleSet {
+    titles: HashSet<String>,
+    /// The (normalized) titles which have been found
+    found: Mutex<HashSet<String>>,
+}
+impl TitleSet {
+    /// Load the titles from the specified file, ignoring blank lines
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read titles from {}: {}", path.display(), e))?;
+        let titles = contents
+            .lines()
+            .map(normalize_title)
+            .filter(|title| !title.is_empty())
+            .collect();
+        Ok(TitleSet {
+            titles,
+            found: Mutex::new(HashSet::new()),
+        })
+    }
+    /// The number of requested titles
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.titles.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.titles.is_empty()
+    }
+    /// Check if the specified article name was requested, recording it as found if it was
+    pub fn contains(&self, name: &str) -> bool {
+        let name = normalize_title(name);
+        if self.titles.contains(&name) {
+            self.found.lock().unwrap().insert(name);
+            true
+        } else {
+            false
+        }
+    }
+    /// Print how many of the requested titles were found
+    pub fn report(&self) {
+        let found = self.found.lock().unwrap();
+        info!(
+            "Found {} of {} requested titles ({} missing)",
+            found.len(),
+            self.len(),
+            self.len() - found.len()
+        );
+        let mut missing = self
+            .titles
+            .iter()
+            .filter(|title| !found.contains(*title))
+            .collect::<Vec<_>>();
+        missing.sort();
+        for title in missing.iter().take(MAX_REPORTED_MISSING) {
+            info!("  Missing {:?}", title);
+        }
+        if missing.len() > MAX_REPORTED_MISSING {
+            info!("  ... and {} more", missing.len() - MAX_REPORTED_MISSING);
+        }
+    }
+}
+
+/// Normalize an article title for comparison.
+///
+/// This decodes percent escapes (as in the article URLs),
+/// treats underscores as spaces and trims surrounding whitespace.
+pub fn normalize_title(title: &str) -> String {
+    percent_encoding::percent_decode_str(title.trim())
+        .decode_utf8_lossy()
+        .replace('_', " ")
+        .trim()
+        .to_string()
+}
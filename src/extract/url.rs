@@ -0,0 +1,70 @@
+//! Parsing the urls of articles (`https://de.wikipedia.org/wiki/Foo`)
+use log::warn;
+use percent_encoding::percent_decode_str;
+
+/// The parts of an article url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArticleUrl {
+    /// The (percent-decoded) title of the article
+    pub title: String,
+    /// The language code of the wiki (the `de` of `de.wikipedia.org`)
+    ///
+    /// This is `None` for relative urls and urls which aren't on wikipedia.
+    pub lang: Option<String>,
+}
+
+/// Parse the title and language of an article from its url
+///
+/// The title is percent-decoded, leaving any invalid percent sequences as-is.
+pub fn parse_url(url: &str) -> Result<ArticleUrl, String> {
+    const PREFIX: &str = "/wiki/";
+    let (before, encoded) = match url.find(PREFIX) {
+        None => return Err(format!("No `/wiki/` in {:?}", url)),
+        Some(idx) => (&url[..idx], &url[idx + PREFIX.len()..]),
+    };
+    if has_invalid_percent(encoded) {
+        warn!("Invalid percent-encoding in {:?}", url);
+    }
+    let title = match percent_decode_str(encoded).decode_utf8() {
+        Ok(title) => title.into_owned(),
+        Err(_) => {
+            warn!("Percent-encoded title isn't valid UTF-8: {:?}", url);
+            encoded.to_string()
+        }
+    };
+    Ok(ArticleUrl {
+        title,
+        lang: parse_lang(before),
+    })
+}
+
+/// Parse the language code from the part of the url before the path
+///
+/// Mobile hosts (`de.m.wikipedia.org`) have the same language.
+fn parse_lang(before_path: &str) -> Option<String> {
+    let host = before_path.split_once("//")?.1;
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    let subdomain = host
+        .to_ascii_lowercase()
+        .strip_suffix(".wikipedia.org")?
+        .to_string();
+    let lang = subdomain.split('.').next()?;
+    if lang.is_empty()
+        || lang == "www"
+        || !lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return None;
+    }
+    Some(lang.to_string())
+}
+
+/// Check for a `%` which isn't followed by two hex digits
+fn has_invalid_percent(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes.iter().enumerate().any(|(i, &b)| {
+        b == b'%'
+            && !(bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+                && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit))
+    })
+}
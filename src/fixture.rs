@@ -0,0 +1,88 @@
+//! Deterministic synthetic articles, for the benchmarks and tests
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::extract::{Article, ArticleBody, Namespace};
+
+/// The words the synthetic text is made of
+const WORDS: &[&str] = &[
+    "the",
+    "of",
+    "and",
+    "in",
+    "was",
+    "city",
+    "river",
+    "century",
+    "history",
+    "population",
+    "government",
+    "music",
+    "album",
+    "species",
+    "university",
+    "war",
+    "church",
+    "station",
+    "football",
+    "season",
+    "born",
+    "known",
+    "first",
+    "later",
+    "between",
+    "during",
+];
+
+/// Generate the specified number of synthetic articles, as an NDJSON dump.
+///
+/// The same seed always generates the same articles.
+/// Each article has a unique name and a few KiB of HTML
+/// (paragraphs of text with internal links), roughly like a short real article.
+pub fn gen_fixture(count: usize, seed: u64) -> String {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut dump = String::new();
+    for index in 0..count {
+        let article = gen_article(&mut rng, index);
+        dump.push_str(&serde_json::to_string(&article).unwrap());
+        dump.push('\n');
+    }
+    dump
+}
+
+/// Generate a single synthetic article, with a name unique to its index
+pub fn gen_article(rng: &mut impl Rng, index: usize) -> Article {
+    let name = format!("Synthetic article {}", index);
+    let mut html = format!(
+        "<html><head><title>{}</title></head><body><div class=\"mw-parser-output\">",
+        name
+    );
+    for _ in 0..rng.random_range(3..12) {
+        html.push_str("<p>");
+        for i in 0..rng.random_range(20..80) {
+            let word = WORDS[rng.random_range(0..WORDS.len())];
+            if i > 0 {
+                html.push(' ');
+            }
+            if rng.random_ratio(1, 15) {
+                let target = rng.random_range(0..=index);
+                html.push_str(&format!(
+                    "<a href=\"/wiki/Synthetic_article_{}\">{}</a>",
+                    target, word
+                ));
+            } else {
+                html.push_str(word);
+            }
+        }
+        html.push_str(".</p>");
+    }
+    html.push_str("</div></body></html>");
+    Article {
+        url: format!("https://en.wikipedia.org/wiki/{}", name.replace(' ', "_")),
+        name,
+        identifier: Some(index as u64 + 1),
+        namespace: Some(Namespace { identifier: 0 }),
+        redirect: None,
+        body: ArticleBody { html },
+    }
+}
@@ -0,0 +1,193 @@
+//! Loads a directory of extracted files (from `extract-files` or `to-files`) into a database
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use log::{debug, info, warn};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use rusqlite::OptionalExtension;
+
+use crate::db::{self, ZSTD_COMPRESSION};
+use crate::extract::files::{desanitize_name, Sanitize};
+use crate::extract::sql::parse_compression_level;
+
+/// The language directory used by `--by-language` for articles without a known language
+const UNKNOWN_LANGUAGE: &str = "unknown";
+
+/// The bytes which are percent-encoded in a synthesized article url
+const URL_TITLE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?');
+
+#[derive(Debug, Args)]
+pub struct FromFilesCommand {
+    /// The directory of (possibly nested) `.html` files to load
+    #[clap(required = true, parse(from_os_str))]
+    input_dir: PathBuf,
+    /// The output database (created if it doesn't exist)
+    #[clap(long = "out", required = true, parse(from_os_str))]
+    output: PathBuf,
+    /// How the article names were turned into file names
+    ///
+    /// Only `percent` and `windows` can be reversed exactly,
+    /// so names recovered from `legacy` file names are a best guess.
+    #[clap(long, arg_enum, default_value = "legacy")]
+    sanitize: Sanitize,
+    /// The files were extracted with `--by-language`,
+    /// so their top level directory is the language of their wiki
+    #[clap(long)]
+    by_language: bool,
+    /// The language of the wiki to synthesize urls for,
+    /// when the language of the article isn't known
+    #[clap(long, default_value = "en")]
+    default_lang: String,
+    /// The zstd compression level to store article bodies with (1-22)
+    #[clap(
+        long,
+        default_value = "1",
+        parse(try_from_str = parse_compression_level)
+    )]
+    compression_level: i32,
+    /// The number of articles to insert per transaction
+    #[clap(long, default_value = "500")]
+    batch_size: u64,
+}
+
+/// The totals counted while loading the files
+#[derive(Default)]
+struct LoadCount {
+    inserted: u64,
+    /// The number of articles which already existed in the database
+    skipped: u64,
+    /// The number of articles whose url had to be synthesized
+    synthesized_urls: u64,
+}
+
+pub fn main(command: FromFilesCommand) -> anyhow::Result<()> {
+    if !command.input_dir.is_dir() {
+        bail!("Not a directory: {}", command.input_dir.display());
+    }
+    if command.sanitize == Sanitize::Legacy {
+        warn!("Legacy file names are lossy, so some article names may not be recovered exactly");
+    }
+    let mut connection = db::open_or_create(&command.output)?;
+    let mut count = LoadCount::default();
+    let mut batch = connection.transaction()?;
+    let mut batch_len = 0u64;
+    for entry in walkdir::WalkDir::new(&command.input_dir).sort_by_file_name() {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension() != Some("html".as_ref()) {
+            continue;
+        }
+        load_file(&batch, &command, path, &mut count)
+            .map_err(|e| anyhow!("Failed to load {}: {}", path.display(), e))?;
+        batch_len += 1;
+        if batch_len >= command.batch_size {
+            batch.commit()?;
+            batch = connection.transaction()?;
+            batch_len = 0;
+            info!("Loaded {} files", count.inserted + count.skipped);
+        }
+    }
+    batch.commit()?;
+    info!("Creating indexes");
+    db::create_indexes(&connection)?;
+    if count.synthesized_urls > 0 {
+        warn!(
+            "Synthesized the urls of {} articles on the {:?} wiki, since their language isn't known",
+            count.synthesized_urls, command.default_lang
+        );
+    }
+    info!(
+        "Loaded {} articles into {} ({} already existed)",
+        count.inserted,
+        command.output.display(),
+        count.skipped
+    );
+    Ok(())
+}
+
+fn load_file(
+    tx: &rusqlite::Transaction,
+    command: &FromFilesCommand,
+    path: &Path,
+    count: &mut LoadCount,
+) -> anyhow::Result<()> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| anyhow!("File name isn't valid UTF-8"))?;
+    // The sanitized name is the title from the url, which uses underscores instead of spaces
+    let title = desanitize_name(stem, command.sanitize)?;
+    let name = title.replace('_', " ");
+    let lang = if command.by_language {
+        language_dir(&command.input_dir, path).filter(|lang| lang != UNKNOWN_LANGUAGE)
+    } else {
+        None
+    };
+    if lang.is_none() {
+        debug!("Synthesizing the url of {:?}", name);
+        count.synthesized_urls += 1;
+    }
+    let url = format!(
+        "https://{}.wikipedia.org/wiki/{}",
+        lang.as_deref().unwrap_or(&command.default_lang),
+        utf8_percent_encode(&title, URL_TITLE)
+    );
+    let html = std::fs::read_to_string(path)?;
+    let article_id = tx
+        .prepare_cached(
+            "INSERT INTO article(name, url, lang, name_key) VALUES (?1, ?2, ?3, name_key(?1)) \
+            ON CONFLICT DO NOTHING RETURNING id",
+        )?
+        .query_row(rusqlite::params![&name, &url, &lang], |row| {
+            row.get::<_, i64>(0)
+        })
+        .optional()?;
+    let article_id = match article_id {
+        Some(article_id) => article_id,
+        None => {
+            debug!("Skipping existing article {:?}", name);
+            count.skipped += 1;
+            return Ok(());
+        }
+    };
+    let compressed_html = zstd::bulk::compress(html.as_bytes(), command.compression_level)?;
+    tx.prepare_cached(
+        "INSERT INTO article_body(article_id, compressed_html, compression, content_sha256) \
+        VALUES(?1, ?2, ?3, ?4)",
+    )?
+    .execute(rusqlite::params![
+        &article_id,
+        &compressed_html,
+        ZSTD_COMPRESSION,
+        db::content_sha256(&html),
+    ])?;
+    tx.prepare_cached(
+        "INSERT INTO meta(key, value) VALUES(?1, 1) \
+        ON CONFLICT(key) DO UPDATE SET value = value + 1",
+    )?
+    .execute([db::ARTICLE_COUNT_KEY])?;
+    count.inserted += 1;
+    Ok(())
+}
+
+/// The top level directory of the file (under the input directory),
+/// which is the language of its wiki with `--by-language`.
+fn language_dir(input_dir: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(input_dir).ok()?;
+    let mut components = relative.components();
+    let first = components.next()?;
+    // The file itself isn't a language directory
+    components.next()?;
+    match first {
+        Component::Normal(dir) => dir.to_str().map(String::from),
+        _ => None,
+    }
+}
@@ -0,0 +1,84 @@
+//! Reads a single article from an NDJSON dump, using the offsets recorded by the `index` command
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use clap::Args;
+
+use crate::extract::Article;
+
+#[derive(Debug, Args)]
+pub struct GetAtCommand {
+    /// The (uncompressed) NDJSON file containing the article
+    #[clap(required = true, parse(from_os_str))]
+    file: PathBuf,
+    /// The byte offset of the article (from the index)
+    #[clap(long)]
+    offset: u64,
+    /// The length of the article in bytes (from the index)
+    #[clap(long)]
+    length: u64,
+    /// Print the JSON of the article, instead of its HTML
+    #[clap(long)]
+    json: bool,
+}
+
+pub fn main(command: GetAtCommand) -> anyhow::Result<()> {
+    let raw = read_at(&command.file, command.offset, command.length)?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if command.json {
+        out.write_all(raw.trim_ascii_start())?;
+        out.write_all(b"\n")?;
+    } else {
+        let article: Article = serde_json::from_slice(&raw).map_err(|e| {
+            anyhow!(
+                "Invalid article at offset {} of {}: {}",
+                command.offset,
+                command.file.display(),
+                e
+            )
+        })?;
+        out.write_all(article.body.html.as_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Read the specified range of bytes from the file
+pub fn read_at(path: &Path, offset: u64, length: u64) -> anyhow::Result<Vec<u8>> {
+    let mut file =
+        File::open(path).map_err(|e| anyhow!("Failed to open file {}: {}", path.display(), e))?;
+    read_range(&mut file, path, offset, length)
+}
+
+/// Read the specified range of bytes from an open file (also used by the [OffsetIndexReader])
+///
+/// [OffsetIndexReader]: crate::index::OffsetIndexReader
+pub(crate) fn read_range(
+    file: &mut File,
+    path: &Path,
+    offset: u64,
+    length: u64,
+) -> anyhow::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset)).map_err(|e| {
+        anyhow!(
+            "Failed to seek to offset {} of {}: {}",
+            offset,
+            path.display(),
+            e
+        )
+    })?;
+    let mut raw = vec![0; length as usize];
+    file.read_exact(&mut raw).map_err(|e| {
+        anyhow!(
+            "Failed to read {} bytes at offset {} of {}: {}",
+            length,
+            offset,
+            path.display(),
+            e
+        )
+    })?;
+    Ok(raw)
+}
@@ -0,0 +1,184 @@
+//! Helpers for processing the article HTML
+use std::collections::HashSet;
+
+use ego_tree::iter::Edge;
+use percent_encoding::percent_decode_str;
+use scraper::{ElementRef, Html, Node, Selector};
+
+/// Elements whose content is never visible text
+const INVISIBLE_ELEMENTS: &[&str] = &["head", "script", "style", "noscript", "template"];
+
+/// Elements which don't separate words.
+///
+/// All other elements (`<p>`, `<li>`, `<td>`, etc.) are treated as word breaks.
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "dfn", "em", "font", "i", "kbd",
+    "mark", "q", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var",
+];
+
+/// Elements removed by [clean]
+const CLEANED_ELEMENTS: &[&str] = &["script", "style"];
+/// Classes whose elements are removed by [clean] (edit links and navigation boxes)
+const CLEANED_CLASSES: &[&str] = &["mw-editsection", "navbox"];
+
+/// The prefix of internal links to other articles
+const WIKILINK_PREFIX: &str = "/wiki/";
+
+/// Parse the HTML of an article, so it can be passed to the other helpers
+#[inline]
+pub fn parse(html: &str) -> Html {
+    Html::parse_document(html)
+}
+
+/// Find the main content of the article (`<div class="mw-parser-output">`),
+/// without the rest of the MediaWiki chrome.
+pub fn content_root(document: &Html) -> Option<ElementRef<'_>> {
+    let selector = Selector::parse("div.mw-parser-output").unwrap();
+    document.select(&selector).next()
+}
+
+/// Extract the visible text content from the specified element
+/// (usually the [Html::root_element] of the document).
+///
+/// The content of `<script>` and `<style>` (and other invisible elements) is dropped,
+/// and runs of whitespace are collapsed into a single space.
+pub fn extract_text(root: ElementRef) -> String {
+    let mut text = TextBuilder::with_capacity(4096);
+    // The depth of nesting inside an invisible element
+    let mut hidden_depth = 0u32;
+    for edge in root.traverse() {
+        match edge {
+            Edge::Open(node) => match node.value() {
+                Node::Element(elem)
+                    if hidden_depth > 0 || INVISIBLE_ELEMENTS.contains(&elem.name()) =>
+                {
+                    hidden_depth += 1;
+                }
+                Node::Element(elem) if !INLINE_ELEMENTS.contains(&elem.name()) => {
+                    text.word_break();
+                }
+                Node::Text(contents) if hidden_depth == 0 => text.push_str(contents),
+                _ => {}
+            },
+            Edge::Close(node) => {
+                if let Node::Element(elem) = node.value() {
+                    if hidden_depth > 0 {
+                        hidden_depth -= 1;
+                    } else if !INLINE_ELEMENTS.contains(&elem.name()) {
+                        text.word_break();
+                    }
+                }
+            }
+        }
+    }
+    text.text
+}
+
+/// Remove the elements which are never wanted from the document,
+/// so that it can be reserialized with [Html::html].
+///
+/// This removes `<script>` and `<style>`, along with edit links (`mw-editsection`)
+/// and navigation boxes (`navbox`).
+pub fn clean(document: &mut Html) {
+    let removed = document
+        .tree
+        .root()
+        .descendants()
+        .filter(|node| match node.value() {
+            Node::Element(elem) => {
+                CLEANED_ELEMENTS.contains(&elem.name())
+                    || elem.classes().any(|class| CLEANED_CLASSES.contains(&class))
+            }
+            _ => false,
+        })
+        .map(|node| node.id())
+        .collect::<Vec<_>>();
+    for id in removed {
+        document.tree.get_mut(id).unwrap().detach();
+    }
+}
+
+/// Extract the text of the lead paragraph under the specified element,
+/// for use as a summary of the article.
+///
+/// This is the text of the first `<p>` which isn't empty (or only a parenthetical),
+/// skipping any paragraphs inside tables (like infoboxes) or hatnotes.
+pub fn extract_summary(root: ElementRef) -> Option<String> {
+    let selector = Selector::parse("p").unwrap();
+    root.select(&selector)
+        .filter(|p| {
+            !p.ancestors().any(|node| match node.value() {
+                Node::Element(elem) => {
+                    elem.name() == "table" || elem.classes().any(|class| class == "hatnote")
+                }
+                _ => false,
+            })
+        })
+        .map(extract_text)
+        .find(|text| {
+            let text = text.trim();
+            let parenthetical = text.starts_with('(') && text.ends_with(')');
+            !text.is_empty() && !parenthetical
+        })
+}
+
+/// Extract the titles of all the internal links (`<a href="/wiki/...">`) in the specified element.
+///
+/// Any `#fragment` or `?query` is removed from the title, then it is percent-decoded
+/// and underscores are replaced with spaces, so that it matches the name of the article
+/// (`C%2B%2B` becomes `C++` and `Foo_bar` becomes `Foo bar`).
+/// The titles are deduplicated, preserving the order of their first occurrence.
+pub fn extract_wikilinks(root: ElementRef) -> Vec<String> {
+    let selector = Selector::parse("a[href]").unwrap();
+    let mut seen = HashSet::new();
+    let mut links = Vec::new();
+    for elem in root.select(&selector) {
+        let href = elem.value().attr("href").unwrap();
+        let title = match href.strip_prefix(WIKILINK_PREFIX) {
+            Some(title) => title,
+            None => continue,
+        };
+        let title = match title.find(['#', '?']) {
+            Some(idx) => &title[..idx],
+            None => title,
+        };
+        let title = percent_decode_str(title)
+            .decode_utf8_lossy()
+            .replace('_', " ");
+        if !title.is_empty() && seen.insert(title.clone()) {
+            links.push(title);
+        }
+    }
+    links
+}
+
+/// Builds text, collapsing runs of whitespace into a single space
+struct TextBuilder {
+    text: String,
+    pending_space: bool,
+}
+impl TextBuilder {
+    fn with_capacity(capacity: usize) -> Self {
+        TextBuilder {
+            text: String::with_capacity(capacity),
+            pending_space: false,
+        }
+    }
+    #[inline]
+    fn word_break(&mut self) {
+        self.pending_space = true;
+    }
+    fn push_str(&mut self, s: &str) {
+        for c in s.chars() {
+            if c.is_whitespace() {
+                self.pending_space = true;
+            } else {
+                if self.pending_space && !self.text.is_empty() {
+                    self.text.push(' ');
+                }
+                self.pending_space = false;
+                self.text.push(c);
+            }
+        }
+    }
+}
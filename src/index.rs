@@ -1,107 +1,596 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Result};
-use clap::Args;
+use anyhow::{anyhow, bail, Result};
+use clap::{ArgEnum, Args};
+use crossbeam::channel::{Receiver, Sender};
+use log::{error, info};
 use serde::ser::{SerializeSeq, Serializer};
 use serde_json::StreamDeserializer;
 
+use crate::extract::Article;
+
 #[derive(Debug, Args)]
 pub struct IndexCommand {
     /// The target directory to put indexed files into
     #[clap(long = "out", parse(from_os_str))]
     out_dir: Option<PathBuf>,
+    /// The number of files to index at once
+    #[clap(long = "workers", short = 'j', default_value = "4")]
+    workers: u32,
+    /// The format of the index files
+    ///
+    /// The `json` format writes a single array to `<stem>-index.json`,
+    /// while `ndjson` writes one object per line to `<stem>-index.ndjson`.
+    #[clap(long, arg_enum, default_value = "json")]
+    format: IndexFormat,
+    /// Write the index into an SQLite database instead of JSON files
+    ///
+    /// The database has a single `article_index(name, url, source_file, offset, length)` table,
+    /// indexed by name. The articles are appended to any existing index.
+    #[clap(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["out-dir", "format"]
+    )]
+    sqlite: Option<PathBuf>,
+    /// Write a single deduplicated `index.json` (or `index.ndjson`), instead of one index per file
+    ///
+    /// If an article appears in multiple files, the first one to be indexed is kept,
+    /// which depends on the order the workers finish in (unless using `-j1`).
+    /// Each entry records the `source_file` it came from.
+    #[clap(long, conflicts_with = "sqlite")]
+    merge: bool,
+    /// Sort the entries of each index by name, so the output is deterministic
+    ///
+    /// Names are compared byte-by-byte, which is the same as ordering by Unicode code point.
+    /// NOTE: This has to buffer the entire index of a file (or the merged index) in memory.
+    #[clap(long, conflicts_with = "sqlite")]
+    sort: bool,
+    /// Don't write a `CACHEDIR.TAG` and `extraction-meta.json` into the output directory
+    ///
+    /// The tag tells backup tools that the contents are regenerable.
+    #[clap(long)]
+    no_cachedir_tag: bool,
     /// The files to index
+    ///
+    /// Directories and glob patterns are expanded into the input files they contain.
+    /// Compressed files can't be indexed, since the offsets are into the raw bytes of the file.
     #[clap(required = true, parse(from_os_str))]
     targets: Vec<PathBuf>,
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+/// The format of the index files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum IndexFormat {
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+impl IndexFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            IndexFormat::Json => "json",
+            IndexFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct ArticleMetadata {
-    name: String,
-    url: String,
+    pub name: String,
+    pub url: String,
+    /// The byte offset of the article within its (uncompressed) source file
+    ///
+    /// This is filled in by the indexer, since it isn't part of the article.
+    #[serde(default)]
+    pub offset: u64,
+    /// The length of the article in bytes (including any leading whitespace)
+    #[serde(default)]
+    pub length: u64,
+    /// The file containing the article (only in merged indexes)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+}
+
+/// Looks up articles in a dump by name, using the byte offsets in its `<stem>-index.json`.
+///
+/// This is the library counterpart of the `get-at` command.
+///
+/// The offsets are into the raw bytes of the dump,
+/// so this only works for plain (uncompressed) dumps.
+/// A compressed dump must be decompressed before it is indexed.
+pub struct OffsetIndexReader {
+    dump: File,
+    dump_path: PathBuf,
+    /// The (offset, length) of each article, by name
+    offsets: HashMap<String, (u64, u64)>,
+}
+impl OffsetIndexReader {
+    /// Load the specified index (as written by `index --format json`) of the dump
+    ///
+    /// If an article appears multiple times, the last occurrence wins.
+    pub fn open(index: &Path, dump: &Path) -> Result<Self> {
+        let f = File::open(index)
+            .map_err(|e| anyhow!("Failed to open index {}: {}", index.display(), e))?;
+        let entries: Vec<ArticleMetadata> = serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| anyhow!("Invalid index {}: {}", index.display(), e))?;
+        let mut dump_file = File::open(dump)
+            .map_err(|e| anyhow!("Failed to open file {}: {}", dump.display(), e))?;
+        if is_compressed_file(&mut dump_file)? {
+            bail!(
+                "Unable to seek into compressed file {}, decompress it first",
+                dump.display()
+            );
+        }
+        let offsets = entries
+            .into_iter()
+            .map(|meta| (meta.name, (meta.offset, meta.length)))
+            .collect();
+        Ok(OffsetIndexReader {
+            dump: dump_file,
+            dump_path: dump.to_path_buf(),
+            offsets,
+        })
+    }
+    /// The number of articles in the index
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+    /// Read the article with the specified name from the dump,
+    /// returning `None` if it isn't in the index.
+    pub fn get(&mut self, name: &str) -> Result<Option<Article>> {
+        let (offset, length) = match self.offsets.get(name) {
+            Some(&location) => location,
+            None => return Ok(None),
+        };
+        let raw = crate::get_at::read_range(&mut self.dump, &self.dump_path, offset, length)?;
+        let article = serde_json::from_slice(&raw).map_err(|e| {
+            anyhow!(
+                "Invalid article {} at offset {} of {}: {}",
+                name,
+                offset,
+                self.dump_path.display(),
+                e
+            )
+        })?;
+        Ok(Some(article))
+    }
+}
+
+/// Check if the file starts with the magic bytes of a compressed format
+fn is_compressed_file(file: &mut File) -> std::io::Result<bool> {
+    let mut header = [0u8; 4];
+    let header_len = file.read(&mut header)?;
+    Ok(crate::extract::is_compressed(&header[..header_len]))
 }
 
 pub fn main(command: IndexCommand) -> anyhow::Result<()> {
+    if command.workers == 0 {
+        bail!("Must have at least one worker");
+    }
+    let targets = crate::extract::discover_targets(&command.targets)?;
+    // The offsets would be into the decompressed stream, which can't be seeked into
+    for target in &targets {
+        let mut f = File::open(target)
+            .map_err(|e| anyhow!("Failed to open file {}: {}", target.display(), e))?;
+        if is_compressed_file(&mut f)? {
+            bail!(
+                "Unable to index compressed file {}, decompress it first",
+                target.display()
+            );
+        }
+    }
     let out_dir = command
         .out_dir
         .clone()
         .unwrap_or_else(|| PathBuf::from("index"));
-    std::fs::create_dir_all(&out_dir)?;
+    let mut sqlite_writer = None;
+    let mut merged_writer = None;
+    match command.sqlite {
+        Some(ref path) => {
+            let conn = open_sqlite(path)?;
+            let (sender, receiver) = crossbeam::channel::bounded::<IndexRow>(ROW_CHANNEL_BOUND);
+            let handle = std::thread::Builder::new()
+                .name("index-sqlite-writer".into())
+                .spawn(move || write_sqlite(conn, receiver))?;
+            sqlite_writer = Some((sender, handle));
+        }
+        None => std::fs::create_dir_all(&out_dir)?,
+    }
+    if command.merge {
+        let path = out_dir.join(format!("index.{}", command.format.extension()));
+        let out = File::create(&path)
+            .map_err(|e| anyhow!("Failed to create file {}: {}", path.display(), e))?;
+        let (sender, receiver) = crossbeam::channel::bounded::<ArticleMetadata>(ROW_CHANNEL_BOUND);
+        let format = command.format;
+        let sort = command.sort;
+        let handle = std::thread::Builder::new()
+            .name("index-merge-writer".into())
+            .spawn(move || {
+                if sort {
+                    let mut entries = receiver.iter().collect::<Vec<_>>();
+                    sort_entries(&mut entries);
+                    write_entries(out, format, entries)
+                } else {
+                    write_entries(out, format, receiver)
+                }
+            })?;
+        let merge = Arc::new(MergeState {
+            path,
+            entries: sender,
+            seen: Mutex::new(HashSet::new()),
+            duplicates: AtomicU64::new(0),
+        });
+        merged_writer = Some((merge, handle));
+    }
     let count = Arc::new(AtomicU64::new(0));
+    let (sender, receiver) = crossbeam::channel::bounded::<IndexJob>(command.workers as usize);
     let mut handles = Vec::new();
-    for target in command.targets {
+    for index in 0..command.workers {
+        let receiver = receiver.clone();
+        let count = Arc::clone(&count);
+        handles.push(
+            std::thread::Builder::new()
+                .name(format!("index-worker-{}", index))
+                .spawn(move || {
+                    let mut failed = 0u64;
+                    while let Ok(job) = receiver.recv() {
+                        let target = job.target.clone();
+                        if let Err(e) = index_file(job, &count) {
+                            error!("Failed to index {}: {}", target.display(), e);
+                            failed += 1;
+                        }
+                    }
+                    failed
+                })?,
+        );
+    }
+    let target_count = targets.len();
+    for target in targets.iter().cloned() {
         let file_name = target
             .file_stem()
             .ok_or_else(|| anyhow!("Expected file name for {}", target.display()))?
             .to_string_lossy()
             .into_owned();
-        let out_file = out_dir.join(format!("{}-index.json", &file_name));
-        let count = Arc::clone(&count);
-        handles.push(std::thread::spawn(handle_errors(move || {
-            let f = File::open(&target)
-                .map_err(|e| anyhow!("Failed to open file {}: {}", target.display(), e))?;
-            let f = BufReader::new(f);
-            let stream: StreamDeserializer<_, ArticleMetadata> =
-                serde_json::de::Deserializer::from_reader(f).into_iter();
-            let out = File::create(&out_file).map_err(|e| {
-                anyhow!("Error: Failed to create file {}: {}", out_file.display(), e)
-            })?;
-            let out = BufWriter::new(out);
-            let mut ser = serde_json::Serializer::new(out);
+        let output = match (&command.sqlite, &sqlite_writer, &merged_writer) {
+            (Some(path), Some((rows, _)), _) => IndexOutput::Sqlite {
+                path: path.clone(),
+                rows: rows.clone(),
+            },
+            (_, _, Some((merge, _))) => IndexOutput::Merged(Arc::clone(merge)),
+            _ => IndexOutput::File {
+                path: out_dir.join(format!(
+                    "{}-index.{}",
+                    &file_name,
+                    command.format.extension()
+                )),
+                format: command.format,
+                sort: command.sort,
+            },
+        };
+        sender
+            .send(IndexJob {
+                target,
+                file_name,
+                output,
+            })
+            .map_err(|_| anyhow!("All the workers have stopped"))?;
+    }
+    drop(sender);
+    // NOTE: The other workers keep indexing after a file fails
+    let mut failed = 0u64;
+    for handle in handles {
+        failed += handle
+            .join()
+            .map_err(|_e| anyhow!("Failed to run thread"))?;
+    }
+    if let Some((rows, handle)) = sqlite_writer {
+        drop(rows);
+        handle
+            .join()
+            .map_err(|_e| anyhow!("Failed to run thread"))??;
+    }
+    if let Some((merge, handle)) = merged_writer {
+        let duplicates = merge.duplicates.load(Ordering::SeqCst);
+        // Drop the last sender, so the writer sees the end of the entries
+        drop(merge);
+        handle
+            .join()
+            .map_err(|_e| anyhow!("Failed to run thread"))??;
+        if duplicates > 0 {
+            info!("Skipped {} duplicate articles", duplicates);
+        }
+    }
+    info!("Indexed total of {} articles", count.load(Ordering::SeqCst));
+    if command.sqlite.is_none() && !command.no_cachedir_tag {
+        let meta = crate::cachedir::ExtractionMeta::new(&targets, count.load(Ordering::SeqCst));
+        crate::cachedir::mark_output_dir(&out_dir, &meta)?;
+    }
+    if failed > 0 {
+        bail!("Failed to index {} of {} files", failed, target_count);
+    }
+    Ok(())
+}
+
+/// A file to be indexed by one of the workers
+struct IndexJob {
+    target: PathBuf,
+    /// The stem of the target file, used in progress messages
+    file_name: String,
+    output: IndexOutput,
+}
+
+/// Where the index of a file is written to
+enum IndexOutput {
+    /// The `<stem>-index.json` (or `.ndjson`) file
+    File {
+        path: PathBuf,
+        format: IndexFormat,
+        sort: bool,
+    },
+    /// The SQLite writer thread (writing to the specified database)
+    Sqlite {
+        path: PathBuf,
+        rows: Sender<IndexRow>,
+    },
+    /// The single merged index (with `--merge`)
+    Merged(Arc<MergeState>),
+}
+
+/// The state of a merged index, shared between all the workers
+struct MergeState {
+    /// The path of the merged index
+    path: PathBuf,
+    /// The entries to be written by the merged writer thread
+    entries: Sender<ArticleMetadata>,
+    /// The names of all the articles that have been indexed
+    seen: Mutex<HashSet<String>>,
+    /// The number of duplicate articles that have been skipped
+    duplicates: AtomicU64,
+}
+
+/// A row of the `article_index` table
+struct IndexRow {
+    meta: ArticleMetadata,
+    source_file: String,
+}
+
+/// The bound on the number of rows waiting to be written to SQLite
+const ROW_CHANNEL_BOUND: usize = 1000;
+/// The number of rows inserted in each SQLite transaction
+const SQLITE_BATCH_SIZE: u64 = 1000;
+
+/// Open the SQLite index, creating the table if it doesn't exist
+fn open_sqlite(path: &Path) -> Result<rusqlite::Connection> {
+    let conn = rusqlite::Connection::open(path)
+        .map_err(|e| anyhow!("Failed to open database {}: {}", path.display(), e))?;
+    conn.execute_batch(
+        "
+        PRAGMA journal_mode = WAL;
+        CREATE TABLE IF NOT EXISTS article_index(
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            source_file TEXT NOT NULL,
+            offset INTEGER NOT NULL,
+            length INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS article_index_idx_name ON article_index(name);
+    ",
+    )?;
+    Ok(conn)
+}
+
+/// Insert the rows received from the workers, batching them into transactions
+fn write_sqlite(mut conn: rusqlite::Connection, rows: Receiver<IndexRow>) -> Result<()> {
+    let mut batch = conn.transaction()?;
+    let mut batch_len = 0u64;
+    while let Ok(row) = rows.recv() {
+        batch
+            .prepare_cached(
+                "INSERT INTO article_index(name, url, source_file, offset, length) \
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(rusqlite::params![
+                &row.meta.name,
+                &row.meta.url,
+                &row.source_file,
+                &row.meta.offset,
+                &row.meta.length
+            ])?;
+        batch_len += 1;
+        if batch_len >= SQLITE_BATCH_SIZE {
+            batch.commit()?;
+            batch = conn.transaction()?;
+            batch_len = 0;
+        }
+    }
+    batch.commit()?;
+    Ok(())
+}
+
+/// Sort the entries of an index by name (with `--sort`)
+fn sort_entries(entries: &mut [ArticleMetadata]) {
+    // NOTE: Stable, so duplicate names keep their order in the file
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+}
+
+/// Write all the entries into the specified index
+///
+/// This is used by the merged writer thread (receiving the deduplicated entries from the workers)
+/// and for sorted indexes.
+fn write_entries(
+    out: File,
+    format: IndexFormat,
+    entries: impl IntoIterator<Item = ArticleMetadata>,
+) -> Result<()> {
+    let mut out = BufWriter::new(out);
+    match format {
+        IndexFormat::Json => {
+            let mut ser = serde_json::Serializer::new(&mut out);
             let mut seq = ser.serialize_seq(None)?;
-            'streamLoop: for value in stream {
-                match value {
-                    Ok(value) => {
-                        let meta: ArticleMetadata = value;
-                        match seq.serialize_element(&meta) {
-                            Ok(()) => {
-                                let i = count.fetch_add(1, Ordering::SeqCst);
-                                if i % 500 == 0 {
-                                    eprintln!("Indexed {} articles", i);
-                                }
-                                if i % 5000 == 0 {
-                                    eprintln!("Indexed {} in {}", &meta.name, &file_name)
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!(
-                                    "WARNING: Failed to write to {}: {}",
-                                    out_file.display(),
-                                    e
-                                );
-                                continue 'streamLoop;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("WARNING: Failed to read from {}: {}", target.display(), e);
-                        continue 'streamLoop;
-                    }
-                }
+            for meta in entries {
+                seq.serialize_element(&meta)?;
             }
             seq.end()?;
+        }
+        IndexFormat::Ndjson => {
+            for meta in entries {
+                serde_json::to_writer(&mut out, &meta)?;
+                out.write_all(b"\n")?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn index_file(job: IndexJob, count: &AtomicU64) -> Result<()> {
+    let IndexJob {
+        target,
+        file_name,
+        output,
+    } = job;
+    let f = File::open(&target)
+        .map_err(|e| anyhow!("Failed to open file {}: {}", target.display(), e))?;
+    let mut f = BufReader::new(f);
+    // NOTE: The offsets of the articles still count the BOM
+    let start_offset = crate::extract::skip_bom(&mut f)? as u64;
+    let stream: StreamDeserializer<_, ArticleMetadata> =
+        serde_json::de::Deserializer::from_reader(f).into_iter();
+    let (out_file, format, sort) = match output {
+        IndexOutput::File { path, format, sort } => (path, format, sort),
+        IndexOutput::Sqlite { path, rows } => {
+            let source_file = target.to_string_lossy().into_owned();
+            let mut index = IndexedFile {
+                file_name: &file_name,
+                out_file: &path,
+                start_offset,
+                count,
+            };
+            index.write_all(stream, |meta| {
+                rows.send(IndexRow {
+                    meta: meta.clone(),
+                    source_file: source_file.clone(),
+                })
+                .map_err(|_| anyhow!("SQLite writer has stopped"))
+            })?;
+            return Ok(());
+        }
+        IndexOutput::Merged(merge) => {
+            let source_file = target.to_string_lossy().into_owned();
+            let mut index = IndexedFile {
+                file_name: &file_name,
+                out_file: &merge.path,
+                start_offset,
+                count,
+            };
+            index.write_all(stream, |meta| {
+                if !merge.seen.lock().unwrap().insert(meta.name.clone()) {
+                    info!("Skipping duplicate {} in {}", meta.name, source_file);
+                    merge.duplicates.fetch_add(1, Ordering::SeqCst);
+                    return Ok(());
+                }
+                merge
+                    .entries
+                    .send(ArticleMetadata {
+                        source_file: Some(source_file.clone()),
+                        ..meta.clone()
+                    })
+                    .map_err(|_| anyhow!("Merged writer has stopped"))
+            })?;
+            return Ok(());
+        }
+    };
+    let out = File::create(&out_file)
+        .map_err(|e| anyhow!("Error: Failed to create file {}: {}", out_file.display(), e))?;
+    let mut index = IndexedFile {
+        file_name: &file_name,
+        out_file: &out_file,
+        start_offset,
+        count,
+    };
+    if sort {
+        let mut entries = Vec::new();
+        index.write_all(stream, |meta| {
+            entries.push(meta.clone());
             Ok(())
-        })));
+        })?;
+        sort_entries(&mut entries);
+        return write_entries(out, format, entries);
     }
-    for handle in handles {
-        handle
-            .join()
-            .map_err(|_e| anyhow!("Failed to run thread"))?;
+    let mut out = BufWriter::new(out);
+    match format {
+        IndexFormat::Json => {
+            let mut ser = serde_json::Serializer::new(out);
+            let mut seq = ser.serialize_seq(None)?;
+            index.write_all(stream, |meta| Ok(seq.serialize_element(meta)?))?;
+            seq.end()?;
+        }
+        IndexFormat::Ndjson => {
+            index.write_all(stream, |meta| {
+                serde_json::to_writer(&mut out, meta)?;
+                out.write_all(b"\n")?;
+                Ok(())
+            })?;
+            out.flush()?;
+        }
     }
-    eprintln!("Indexed total of {} articles", count.load(Ordering::SeqCst));
     Ok(())
 }
 
-fn handle_errors(func: impl FnOnce() -> Result<(), anyhow::Error>) -> impl FnOnce() {
-    || match func() {
-        Err(e) => {
-            eprintln!("ERROR: {}", e);
-            std::process::exit(1)
+/// The file currently being indexed
+struct IndexedFile<'a> {
+    file_name: &'a str,
+    out_file: &'a Path,
+    /// The offset of the start of the stream within the file (after any BOM)
+    start_offset: u64,
+    count: &'a AtomicU64,
+}
+impl IndexedFile<'_> {
+    /// Write the metadata of every article in the stream,
+    /// including its byte offset within the file.
+    ///
+    /// This stops at the first article which fails to parse or write,
+    /// so the file is reported as failed (the entries before it have already been written).
+    fn write_all<R: serde_json::de::Read<'static>>(
+        &mut self,
+        mut stream: StreamDeserializer<'static, R, ArticleMetadata>,
+        mut write: impl FnMut(&ArticleMetadata) -> Result<()>,
+    ) -> Result<()> {
+        let mut offset = self.start_offset;
+        while let Some(value) = stream.next() {
+            // NOTE: The byte offset is just past the end of the last parsed value
+            let end = self.start_offset + stream.byte_offset() as u64;
+            let start = std::mem::replace(&mut offset, end);
+            let meta = match value {
+                Ok(value) => ArticleMetadata {
+                    offset: start,
+                    length: end - start,
+                    ..value
+                },
+                Err(e) => bail!("Invalid article at byte {}: {}", start, e),
+            };
+            write(&meta)
+                .map_err(|e| anyhow!("Failed to write to {}: {}", self.out_file.display(), e))?;
+            let i = self.count.fetch_add(1, Ordering::SeqCst);
+            if i.is_multiple_of(500) {
+                info!("Indexed {} articles", i);
+            }
+            if i.is_multiple_of(5000) {
+                info!("Indexed {} in {}", &meta.name, self.file_name)
+            }
         }
-        Ok(()) => {}
+        Ok(())
     }
 }
@@ -0,0 +1,39 @@
+//! Extract the HTML from wikipedia "Enterprise HTML Dumps".
+//!
+//! Each command of the `wikipedia-html-extractor` binary is implemented by one of these modules.
+pub mod cachedir;
+pub mod db;
+pub mod ensure_nested;
+pub mod export;
+pub mod extract;
+pub mod fixture;
+pub mod from_files;
+pub mod get_at;
+pub mod html;
+pub mod index;
+pub mod list;
+pub mod merge;
+pub mod nesting;
+pub mod optimize;
+pub mod query;
+pub mod random;
+pub mod reindex;
+#[cfg(feature = "server")]
+pub mod serve;
+pub mod stats;
+pub mod subset;
+pub mod to_files;
+pub mod unnest;
+pub mod verify;
+
+/// The maximum number of worker threads used by default (see [default_workers])
+const MAX_DEFAULT_WORKERS: u32 = 32;
+
+/// The default number of worker threads for the commands with a `--workers` option.
+///
+/// This is the number of available CPUs, clamped to at most [MAX_DEFAULT_WORKERS].
+pub fn default_workers() -> u32 {
+    std::thread::available_parallelism()
+        .map_or(1, |cpus| cpus.get() as u32)
+        .clamp(1, MAX_DEFAULT_WORKERS)
+}
@@ -0,0 +1,76 @@
+//! Lists the articles in a database produced by the `extract` command
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::bail;
+use clap::Args;
+
+use crate::db;
+
+#[derive(Debug, Args)]
+pub struct ListCommand {
+    /// The database to list
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// Print the URL of each article after its name (separated by a tab)
+    #[clap(long)]
+    urls: bool,
+    /// Sort the articles by name, instead of the order they were inserted in
+    #[clap(long)]
+    sort: bool,
+    /// Only list articles whose name matches this SQL `LIKE` pattern
+    ///
+    /// Use `%` to match any sequence of characters and `_` to match a single character.
+    /// Like SQLite, the match is case-insensitive for ASCII.
+    #[clap(long)]
+    like: Option<String>,
+    /// Match the `--like` pattern against the normalized keys of the names,
+    /// which ignore the Unicode normalization form (like `query --normalize`)
+    #[clap(long, requires = "like")]
+    normalize: bool,
+    /// The limit on the number of articles to list
+    #[clap(long)]
+    limit: Option<u64>,
+    /// Skip the first N matching articles
+    #[clap(long, default_value = "0")]
+    offset: u64,
+}
+
+pub fn main(command: ListCommand) -> anyhow::Result<()> {
+    let conn = db::open_readonly(&command.database)?;
+    let (column, like) = if command.normalize {
+        if !db::has_name_keys(&conn)? {
+            bail!("The database has no normalized names, run `reindex` to add them");
+        }
+        let name_key = db::NameKey::load(&conn)?;
+        (
+            "name_key",
+            command.like.as_deref().map(|like| name_key.key(like)),
+        )
+    } else {
+        ("name", command.like.clone())
+    };
+    let sql = format!(
+        "SELECT name, url FROM article WHERE ?1 IS NULL OR {} LIKE ?1 \
+        ORDER BY {} LIMIT ?2 OFFSET ?3",
+        column,
+        if command.sort { "name" } else { "id" }
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    // A negative limit means no limit in SQLite
+    let limit = command.limit.map_or(-1, |limit| limit as i64);
+    let mut rows = stmt.query(rusqlite::params![like, limit, command.offset])?;
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(0)?;
+        if command.urls {
+            let url: String = row.get(1)?;
+            writeln!(out, "{}\t{}", name, url)?;
+        } else {
+            writeln!(out, "{}", name)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -1,14 +1,32 @@
 use clap::{Parser, Subcommand};
+use log::LevelFilter;
 
-mod ensure_nested;
-mod extract;
-mod index;
+#[cfg(feature = "server")]
+use wikipedia_html_extractor::serve;
+use wikipedia_html_extractor::{
+    ensure_nested, export, extract, from_files, get_at, index, list, merge, optimize, query,
+    random, reindex, stats, subset, to_files, unnest, verify,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version)]
 #[clap(about = "Commands to manipulate and analyse wikipedia HTML dumps")]
 #[clap(propagate_version = true)]
 struct Cli {
+    /// Print more information (repeat for even more)
+    ///
+    /// The `RUST_LOG` environment variable overrides this.
+    #[clap(long, short = 'v', global = true, parse(from_occurrences))]
+    verbose: u8,
+    /// Only print warnings and errors (repeat to only print errors)
+    #[clap(
+        long,
+        short = 'q',
+        global = true,
+        parse(from_occurrences),
+        conflicts_with = "verbose"
+    )]
+    quiet: u8,
     #[clap(subcommand)]
     command: Command,
 }
@@ -19,14 +37,66 @@ enum Command {
     EnsureNested(ensure_nested::EnsureNested),
     Extract(extract::sql::ExtractSqlCommand),
     Index(index::IndexCommand),
+    GetAt(get_at::GetAtCommand),
+    Query(query::QueryCommand),
+    Export(export::ExportCommand),
+    Merge(merge::MergeCommand),
+    Verify(verify::VerifyCommand),
+    Optimize(optimize::OptimizeCommand),
+    Reindex(reindex::ReindexCommand),
+    Unnest(unnest::UnnestCommand),
+    Stats(stats::StatsCommand),
+    List(list::ListCommand),
+    Subset(subset::SubsetCommand),
+    ToFiles(to_files::ToFilesCommand),
+    FromFiles(from_files::FromFilesCommand),
+    Random(random::RandomCommand),
+    #[cfg(feature = "server")]
+    Serve(serve::ServeCommand),
 }
 
 pub fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    init_logging(cli.verbose, cli.quiet);
     match cli.command {
-        Command::ExtractFiles(cmd) => extract::files::extract(cmd),
+        Command::ExtractFiles(cmd) => extract::files::extract(cmd).map(drop),
         Command::EnsureNested(cmd) => ensure_nested::main(cmd),
-        Command::Extract(cmd) => extract::sql::extract(cmd),
+        Command::Extract(cmd) => extract::sql::extract(cmd).map(drop),
         Command::Index(cmd) => index::main(cmd),
+        Command::GetAt(cmd) => get_at::main(cmd),
+        Command::Query(cmd) => query::main(cmd),
+        Command::Export(cmd) => export::main(cmd),
+        Command::Merge(cmd) => merge::main(cmd),
+        Command::Verify(cmd) => verify::main(cmd),
+        Command::Optimize(cmd) => optimize::main(cmd),
+        Command::Reindex(cmd) => reindex::main(cmd),
+        Command::Unnest(cmd) => unnest::main(cmd),
+        Command::Stats(cmd) => stats::main(cmd),
+        Command::List(cmd) => list::main(cmd),
+        Command::Subset(cmd) => subset::main(cmd),
+        Command::ToFiles(cmd) => to_files::main(cmd),
+        Command::FromFiles(cmd) => from_files::main(cmd),
+        Command::Random(cmd) => random::main(cmd),
+        #[cfg(feature = "server")]
+        Command::Serve(cmd) => serve::main(cmd),
     }
 }
+
+/// Initialize the logger, with the level selected by `--verbose`/`--quiet`.
+///
+/// Any `RUST_LOG` filters take precedence.
+fn init_logging(verbose: u8, quiet: u8) {
+    let level = match (verbose, quiet) {
+        (0, 0) => LevelFilter::Info,
+        (1, _) => LevelFilter::Debug,
+        (_, 0) => LevelFilter::Trace,
+        (_, 1) => LevelFilter::Warn,
+        (_, _) => LevelFilter::Error,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format_timestamp(None)
+        .format_target(false)
+        .parse_default_env()
+        .init();
+}
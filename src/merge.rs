@@ -0,0 +1,181 @@
+//! Combines multiple databases produced by the `extract` command
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use clap::Args;
+use log::info;
+use rusqlite::{Connection, Transaction};
+
+use crate::db;
+
+#[derive(Debug, Args)]
+pub struct MergeCommand {
+    /// The database to merge into (created if it doesn't exist)
+    #[clap(long = "out", required = true, parse(from_os_str))]
+    output: PathBuf,
+    /// The databases to merge
+    #[clap(required = true, parse(from_os_str))]
+    sources: Vec<PathBuf>,
+}
+
+/// The number of articles merged from a single source database
+pub(crate) struct MergeCount {
+    pub merged: u64,
+    pub skipped: u64,
+}
+
+pub fn main(command: MergeCommand) -> anyhow::Result<()> {
+    let mut connection = db::open_or_create(&command.output)?;
+    db::create_indexes(&connection)?;
+    let mut total_merged = 0u64;
+    let mut total_skipped = 0u64;
+    for source in &command.sources {
+        if !source.is_file() {
+            bail!("Database doesn't exist: {}", source.display());
+        }
+        info!("Merging {}", source.display());
+        let count = merge_source(&mut connection, source, None)?;
+        info!(
+            "Merged {} articles ({} duplicates skipped)",
+            count.merged, count.skipped
+        );
+        total_merged += count.merged;
+        total_skipped += count.skipped;
+    }
+    info!(
+        "Merged {} articles from {} databases, skipping {} duplicates",
+        total_merged,
+        command.sources.len(),
+        total_skipped
+    );
+    Ok(())
+}
+
+/// Merge the articles from the source database into the connection's database.
+///
+/// If there is a filter, only the articles of the source matching it
+/// (an SQL condition on the columns of `src.article`) are merged.
+pub(crate) fn merge_source(
+    connection: &mut Connection,
+    source: &Path,
+    filter: Option<&str>,
+) -> anyhow::Result<MergeCount> {
+    connection.execute(
+        "ATTACH DATABASE ?1 AS src",
+        [source.to_string_lossy().as_ref()],
+    )?;
+    let res = connection
+        .transaction()
+        .map_err(anyhow::Error::from)
+        .and_then(|tx| {
+            let count = merge_attached(&tx, filter.unwrap_or("true"))?;
+            tx.commit()?;
+            Ok(count)
+        });
+    connection.execute_batch("DETACH DATABASE src")?;
+    res
+}
+
+/// Copy the articles from the attached `src` database into the `main` database.
+///
+/// Articles whose names already exist are skipped, just like `serialize_article` does.
+/// Because the ids of the two databases overlap,
+/// each copied article is assigned a new id and the references to it are remapped.
+fn merge_attached(tx: &Transaction, filter: &str) -> anyhow::Result<MergeCount> {
+    let total: i64 = tx.query_row(
+        &format!("SELECT COUNT(*) FROM src.article WHERE {}", filter),
+        [],
+        |row| row.get(0),
+    )?;
+    let before: i64 = tx.query_row("SELECT COALESCE(MAX(id), 0) FROM main.article", [], |row| {
+        row.get(0)
+    })?;
+    // The keys are recomputed, since the source may be older (or lack them)
+    let columns = shared_columns(tx, "article", &["id", "name_key"])?;
+    // NOTE: The WHERE clause (even if just `true`) avoids a parsing ambiguity with ON CONFLICT
+    let merged = tx.execute(
+        &format!(
+            "INSERT INTO main.article({columns}, name_key) \
+            SELECT {columns}, name_key(name) FROM src.article \
+            WHERE {filter} ORDER BY id ON CONFLICT DO NOTHING",
+            columns = columns.join(", "),
+            filter = filter
+        ),
+        [],
+    )? as i64;
+    /*
+     * Every article with an id above the previous maximum was inserted from the source,
+     * so joining those on the (unique) name gives the mapping of the old ids to the new ones.
+     * The key is only compared so the join can use its index.
+     */
+    tx.execute(
+        "CREATE TEMP TABLE merge_id(old_id INTEGER PRIMARY KEY, new_id INTEGER NOT NULL)",
+        [],
+    )?;
+    tx.execute(
+        "INSERT INTO temp.merge_id(old_id, new_id) \
+        SELECT src.article.id, main.article.id FROM src.article \
+        JOIN main.article ON main.article.name_key = name_key(src.article.name) \
+            AND main.article.name = src.article.name \
+        WHERE main.article.id > ?1",
+        [before],
+    )?;
+    let columns = shared_columns(tx, "article_body", &["id", "article_id"])?;
+    tx.execute(
+        &format!(
+            "INSERT INTO main.article_body(article_id, {}) \
+            SELECT merge_id.new_id, {} FROM src.article_body \
+            JOIN temp.merge_id ON merge_id.old_id = src.article_body.article_id",
+            columns.join(", "),
+            columns
+                .iter()
+                .map(|column| format!("src.article_body.{}", column))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        [],
+    )?;
+    if db::has_table(tx, "src", "wikilink")? {
+        db::create_wikilink_table(tx)?;
+        tx.execute(
+            "INSERT INTO main.wikilink(article_id, target_title) \
+            SELECT merge_id.new_id, src.wikilink.target_title FROM src.wikilink \
+            JOIN temp.merge_id ON merge_id.old_id = src.wikilink.article_id",
+            [],
+        )?;
+    }
+    if db::has_table(tx, "src", "article_fts")? {
+        db::create_fts_table(tx)?;
+        tx.execute(
+            "INSERT INTO main.article_fts(rowid, text) \
+            SELECT merge_id.new_id, src.article_fts.text FROM src.article_fts \
+            JOIN temp.merge_id ON merge_id.old_id = src.article_fts.rowid",
+            [],
+        )?;
+    }
+    tx.execute("DROP TABLE temp.merge_id", [])?;
+    tx.execute(
+        "INSERT INTO meta(key, value) VALUES(?1, ?2) \
+        ON CONFLICT(key) DO UPDATE SET value = value + excluded.value",
+        rusqlite::params![db::ARTICLE_COUNT_KEY, merged],
+    )?;
+    Ok(MergeCount {
+        merged: merged as u64,
+        skipped: (total - merged) as u64,
+    })
+}
+
+/// The columns of the specified table present in both databases,
+/// excluding the specified ones.
+///
+/// Older databases may be missing some of the columns.
+fn shared_columns(conn: &Connection, table: &str, exclude: &[&str]) -> anyhow::Result<Vec<String>> {
+    let source = db::columns(conn, "src", table)?;
+    if source.is_empty() {
+        bail!("Source database has no {:?} table", table);
+    }
+    Ok(db::columns(conn, "main", table)?
+        .into_iter()
+        .filter(|column| !exclude.contains(&column.as_str()) && source.contains(column))
+        .collect())
+}
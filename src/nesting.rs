@@ -0,0 +1,81 @@
+//! The nested directory layout shared by `extract-files`, `to-files` and `ensure-nested`.
+//!
+//! Each file is nested `--nest-depth` directories deep (2 by default),
+//! with one character of the directory name per level:
+//!
+//! - `--nest-by prefix` (the default) uses the leading characters of the file name,
+//!   so `Foo.html` is stored as `F/o/Foo.html`.
+//!   Names shorter than the depth are nested less deeply.
+//! - `--nest-by hash` uses the leading hex digits of the SHA-256 hash of the file name,
+//!   so `Foo.html` is stored as something like `3/a/Foo.html`.
+//!   This spreads the files evenly, no matter how many names share a prefix.
+//!
+//! Both commands must be given the same options for their layouts to agree.
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use clap::{ArgEnum, Args};
+
+/// How to choose the nested directories of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum NestBy {
+    /// The leading characters of the file name
+    Prefix,
+    /// The leading hex digits of the hash of the file name
+    Hash,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct NestingOptions {
+    /// The number of directories to nest each file under
+    #[clap(long, default_value = "2")]
+    nest_depth: usize,
+    /// How to choose the nested directories
+    #[clap(long, arg_enum, default_value = "prefix")]
+    nest_by: NestBy,
+}
+impl NestingOptions {
+    /// The directory (under `base`) which the file with the specified name is nested in
+    pub fn nested_dir(&self, base: &Path, name: &str) -> PathBuf {
+        let mut target = PathBuf::from(base);
+        let levels: Vec<char> = match self.nest_by {
+            NestBy::Prefix => name.chars().take(self.nest_depth).collect(),
+            NestBy::Hash => crate::db::content_sha256(name)
+                .chars()
+                .take(self.nest_depth)
+                .collect(),
+        };
+        for level in levels {
+            target.push(String::from(level));
+        }
+        target
+    }
+}
+
+/// A cache of the directories which are known to exist,
+/// avoiding redundant calls to [std::fs::create_dir_all].
+#[derive(Default)]
+pub struct DirCache {
+    existing: Mutex<HashSet<PathBuf>>,
+}
+impl DirCache {
+    pub fn new() -> Self {
+        DirCache::default()
+    }
+    /// Create the specified directory (and its parents), unless it is already known to exist
+    pub fn create_dir_all(&self, dir: &Path) -> io::Result<()> {
+        if self.existing.lock().unwrap().contains(dir) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(dir)?;
+        self.existing.lock().unwrap().insert(dir.to_path_buf());
+        Ok(())
+    }
+    /// Record that the specified directory exists (without creating it),
+    /// returning `false` if it was already known.
+    pub fn insert(&self, dir: &Path) -> bool {
+        self.existing.lock().unwrap().insert(dir.to_path_buf())
+    }
+}
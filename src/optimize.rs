@@ -0,0 +1,59 @@
+//! Compacts and analyzes a database produced by the `extract` command
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use indicatif::HumanBytes;
+use log::info;
+use rusqlite::{Connection, OpenFlags};
+
+#[derive(Debug, Args)]
+pub struct OptimizeCommand {
+    /// The database to optimize
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+}
+
+pub fn main(command: OptimizeCommand) -> anyhow::Result<()> {
+    if !command.database.is_file() {
+        anyhow::bail!("Database doesn't exist: {}", command.database.display());
+    }
+    optimize(&command.database)
+}
+
+/// Checkpoint the write-ahead log, then `VACUUM` and `ANALYZE` the specified database.
+///
+/// This opens a fresh connection, so it must only be called
+/// once every other connection to the database has been closed.
+pub fn optimize(path: &Path) -> anyhow::Result<()> {
+    let before = database_size(path)?;
+    info!("Optimizing {} ({})", path.display(), HumanBytes(before));
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    connection.execute_batch(
+        "
+        PRAGMA wal_checkpoint(TRUNCATE);
+        VACUUM;
+        ANALYZE;
+        PRAGMA wal_checkpoint(TRUNCATE);
+    ",
+    )?;
+    connection.close().map_err(|(_, e)| e)?;
+    let after = database_size(path)?;
+    info!(
+        "Optimized database from {} to {}",
+        HumanBytes(before),
+        HumanBytes(after)
+    );
+    Ok(())
+}
+
+/// The size of the specified database, including its write-ahead log
+fn database_size(path: &Path) -> std::io::Result<u64> {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    let wal_size = match std::fs::metadata(&wal) {
+        Ok(meta) => meta.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+    Ok(std::fs::metadata(path)?.len() + wal_size)
+}
@@ -0,0 +1,110 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use rusqlite::OptionalExtension;
+
+use crate::db;
+
+#[derive(Debug, Args)]
+pub struct QueryCommand {
+    /// The database to query
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// The exact name of the article to fetch
+    #[clap(required_unless_present = "url")]
+    name: Option<String>,
+    /// Lookup the article by its URL instead of its name
+    #[clap(long, conflicts_with = "name")]
+    url: Option<String>,
+    /// Ignore the case (of ASCII letters) when matching the name
+    ///
+    /// This is only fast if the database was extracted with `--nocase-index`.
+    /// If several articles match, the first one is printed.
+    #[clap(long, conflicts_with_all = &["url", "normalize"])]
+    ignore_case: bool,
+    /// Match the name by its normalized key, which articles are deduplicated by
+    ///
+    /// The key ignores the Unicode normalization form of the name,
+    /// so `Café` matches a decomposed `Cafe\u{301}`.
+    /// It also ignores the case if the database was extracted with `--dedup-ignore-case`.
+    #[clap(long, conflicts_with = "url")]
+    normalize: bool,
+    /// Print the SHA-256 hash of the article's HTML, instead of the HTML itself
+    #[clap(long)]
+    sha256: bool,
+    /// Print the word count and reading time of the article, instead of the HTML itself
+    ///
+    /// These are only stored by `extract --word-count`.
+    #[clap(long, conflicts_with = "sha256")]
+    word_count: bool,
+}
+
+pub fn main(command: QueryCommand) -> anyhow::Result<()> {
+    let conn = db::open_readonly(&command.database)?;
+    let (column, key) = match (&command.name, &command.url) {
+        (_, Some(url)) => ("url", url.clone()),
+        (Some(name), None) if command.normalize => {
+            if !db::has_name_keys(&conn)? {
+                bail!("The database has no normalized names, run `reindex` to add them");
+            }
+            ("name_key", db::NameKey::load(&conn)?.key(name))
+        }
+        (Some(name), None) => ("name", name.clone()),
+        (None, None) => unreachable!("clap requires name or url"),
+    };
+    let sql = format!(
+        "SELECT article_body.compressed_html, {}, {}, {}, {} FROM article \
+        JOIN article_body ON article_body.article_id = article.id \
+        WHERE article.{} = ?1{} ORDER BY article.id",
+        db::compression_expr(&conn)?,
+        db::column_or(&conn, "article_body", "content_sha256", "NULL")?,
+        db::column_or(&conn, "article", "word_count", "NULL")?,
+        db::column_or(&conn, "article", "reading_seconds", "NULL")?,
+        column,
+        if command.ignore_case {
+            " COLLATE NOCASE"
+        } else {
+            ""
+        }
+    );
+    let (body, compression, content_sha256, word_count, reading_seconds) = conn
+        .query_row(&sql, [&key], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<u64>>(3)?,
+                row.get::<_, Option<u64>>(4)?,
+            ))
+        })
+        .optional()?
+        .ok_or_else(|| {
+            let label = if command.url.is_some() { "url" } else { "name" };
+            anyhow!("No article with {} {:?}", label, key)
+        })?;
+    let html = db::decode_body(body, &compression)?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if command.sha256 {
+        // Older databases don't store the hash
+        let hash = content_sha256.unwrap_or_else(|| db::content_sha256(&html));
+        writeln!(out, "{}", hash)?;
+    } else if command.word_count {
+        let (word_count, reading_seconds) = match (word_count, reading_seconds) {
+            (Some(word_count), Some(reading_seconds)) => (word_count, reading_seconds),
+            _ => bail!("No word count stored, extract with --word-count"),
+        };
+        writeln!(
+            out,
+            "{} words ({} min read)",
+            word_count,
+            reading_seconds.div_ceil(60)
+        )?;
+    } else {
+        out.write_all(html.as_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! Picks random articles from a database produced by the `extract` command
+use std::collections::HashSet;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rusqlite::OptionalExtension;
+
+use crate::db;
+
+#[derive(Debug, Args)]
+pub struct RandomCommand {
+    /// The database to pick the articles from
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// The number of (distinct) articles to pick
+    #[clap(long, default_value = "1")]
+    count: u64,
+    /// Print the HTML of each article, instead of its name and URL
+    #[clap(long)]
+    html: bool,
+    /// The random seed, for a reproducible selection
+    #[clap(long)]
+    seed: Option<u64>,
+}
+
+pub fn main(command: RandomCommand) -> anyhow::Result<()> {
+    let conn = db::open_readonly(&command.database)?;
+    let (total, min_id, max_id): (u64, Option<i64>, Option<i64>) = conn.query_row(
+        "SELECT COUNT(*), MIN(id), MAX(id) FROM article \
+        WHERE EXISTS(SELECT 1 FROM article_body WHERE article_body.article_id = article.id)",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let (min_id, max_id) = match (min_id, max_id) {
+        (Some(min_id), Some(max_id)) => (min_id, max_id),
+        _ => anyhow::bail!("The database has no articles"),
+    };
+    let mut rng = match command.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    };
+    /*
+     * Picking a random id and taking the first article at or after it
+     * avoids sorting the whole table (like `ORDER BY RANDOM()` would).
+     * Articles after a gap in the ids are slightly more likely to be picked.
+     */
+    let mut stmt = conn.prepare(&format!(
+        "SELECT article.id, article.name, article.url, article_body.compressed_html, {} \
+        FROM article JOIN article_body ON article_body.article_id = article.id \
+        WHERE article.id >= ?1 ORDER BY article.id LIMIT 1",
+        db::compression_expr(&conn)?
+    ))?;
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut picked = HashSet::new();
+    while (picked.len() as u64) < command.count.min(total) {
+        let target = rng.random_range(min_id..=max_id);
+        let row = stmt
+            .query_row([target], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })
+            .optional()?;
+        let (id, name, url, body, compression) = match row {
+            Some(row) => row,
+            // Past the last article with a body
+            None => continue,
+        };
+        if !picked.insert(id) {
+            continue;
+        }
+        if command.html {
+            out.write_all(db::decode_body(body, &compression)?.as_bytes())?;
+            writeln!(out)?;
+        } else {
+            writeln!(out, "{}\t{}", name, url)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
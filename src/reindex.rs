@@ -0,0 +1,30 @@
+//! Adds missing indexes to databases produced by older versions of the `extract` command
+use std::path::PathBuf;
+
+use clap::Args;
+use log::info;
+
+use crate::db;
+
+#[derive(Debug, Args)]
+pub struct ReindexCommand {
+    /// The database to reindex
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+}
+
+pub fn main(command: ReindexCommand) -> anyhow::Result<()> {
+    let conn = db::open_existing(&command.database)?;
+    // Older databases may be missing the indexed columns
+    db::register_functions(&conn)?;
+    db::add_missing_columns(&conn)?;
+    db::add_name_keys(&conn, &command.database)?;
+    let created = db::create_indexes(&conn)?;
+    for name in &created {
+        info!("Created index {}", name);
+    }
+    if created.is_empty() {
+        info!("All indexes already exist");
+    }
+    Ok(())
+}
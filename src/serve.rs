@@ -0,0 +1,193 @@
+//! Serves the articles of a database over HTTP (with the `server` feature)
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use log::{debug, info, warn};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use rusqlite::{Connection, OptionalExtension};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::db;
+
+/// The maximum number of results returned by `/search`
+const MAX_SEARCH_RESULTS: u32 = 50;
+
+#[derive(Debug, Args)]
+pub struct ServeCommand {
+    /// The database to serve
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// The address to listen on
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    bind: String,
+    /// The number of threads handling requests (defaults to the number of CPUs)
+    #[clap(long = "workers", short = 'j')]
+    workers: Option<u32>,
+}
+
+pub fn main(command: ServeCommand) -> anyhow::Result<()> {
+    let workers = command.workers.unwrap_or_else(crate::default_workers);
+    if workers == 0 {
+        bail!("Must have at least one worker");
+    }
+    // Fail early if the database can't be opened
+    let conn = db::open_readonly(&command.database)?;
+    let fts = db::has_table(&conn, "main", "article_fts")?;
+    if !fts {
+        warn!("The database has no full-text index, so /search is unavailable");
+    }
+    drop(conn);
+    let server = Server::http(&command.bind)
+        .map_err(|e| anyhow!("Failed to listen on {}: {}", command.bind, e))?;
+    let server = Arc::new(server);
+    info!(
+        "Serving {} on http://{}",
+        command.database.display(),
+        command.bind
+    );
+    let mut handles = Vec::new();
+    for index in 0..workers {
+        let server = Arc::clone(&server);
+        // SQLite connections can't be shared between threads, so each worker opens its own
+        let conn = db::open_readonly(&command.database)?;
+        handles.push(
+            std::thread::Builder::new()
+                .name(format!("serve-worker-{}", index))
+                .spawn(move || {
+                    for request in server.incoming_requests() {
+                        handle_request(&conn, fts, request);
+                    }
+                })?,
+        );
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    Ok(())
+}
+
+fn handle_request(conn: &Connection, fts: bool, request: Request) {
+    debug!("{} {}", request.method(), request.url());
+    let response = if *request.method() != Method::Get {
+        text_response(405, "Method not allowed")
+    } else {
+        route(conn, fts, request.url()).unwrap_or_else(|e| {
+            warn!("Failed to handle {}: {}", request.url(), e);
+            text_response(500, "Internal server error")
+        })
+    };
+    if let Err(e) = request.respond(response) {
+        debug!("Failed to send response: {}", e);
+    }
+}
+
+fn route(conn: &Connection, fts: bool, url: &str) -> anyhow::Result<Response<Cursor<Vec<u8>>>> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    if let Some(title) = path.strip_prefix("/wiki/") {
+        let title = percent_decode_str(title).decode_utf8_lossy();
+        return Ok(match lookup_article(conn, &title)? {
+            Some(html) => html_response(200, html),
+            None => text_response(404, &format!("No article named {:?}", title)),
+        });
+    }
+    if path == "/search" {
+        if !fts {
+            return Ok(text_response(
+                404,
+                "No full-text index, extract with --fts to enable search",
+            ));
+        }
+        let q = query
+            .split('&')
+            .filter_map(|param| param.strip_prefix("q="))
+            .next()
+            .map(|q| {
+                percent_decode_str(&q.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+            .unwrap_or_default();
+        return search(conn, &q);
+    }
+    Ok(text_response(404, "Not found"))
+}
+
+/// Find the HTML of the article with the specified title,
+/// which may use underscores instead of spaces (like the urls on wikipedia).
+fn lookup_article(conn: &Connection, title: &str) -> anyhow::Result<Option<String>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT article_body.compressed_html, {} FROM article \
+        JOIN article_body ON article_body.article_id = article.id \
+        WHERE article.name = ?1",
+        db::compression_expr(conn)?
+    ))?;
+    for name in [title.to_string(), title.replace('_', " ")] {
+        let body = stmt
+            .query_row([&name], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, String>(1)?))
+            })
+            .optional()?;
+        if let Some((body, compression)) = body {
+            return Ok(Some(db::decode_body(body, &compression)?));
+        }
+    }
+    Ok(None)
+}
+
+/// List the articles matching the full-text search query, as links to them
+fn search(conn: &Connection, q: &str) -> anyhow::Result<Response<Cursor<Vec<u8>>>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT article.name FROM article_fts \
+        JOIN article ON article.id = article_fts.rowid \
+        WHERE article_fts MATCH ?1 ORDER BY rank LIMIT ?2",
+    )?;
+    let names = match stmt
+        .query_map(rusqlite::params![q, MAX_SEARCH_RESULTS], |row| {
+            row.get::<_, String>(0)
+        })
+        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+    {
+        Ok(names) => names,
+        // Most likely a syntax error in the query
+        Err(e) => return Ok(text_response(400, &format!("Invalid query: {}", e))),
+    };
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Search: {}</title></head><body>\n<ul>\n",
+        escape_html(q)
+    );
+    for name in &names {
+        html.push_str(&format!(
+            "<li><a href=\"/wiki/{}\">{}</a></li>\n",
+            utf8_percent_encode(name, NON_ALPHANUMERIC),
+            escape_html(name)
+        ));
+    }
+    html.push_str("</ul>\n</body></html>\n");
+    Ok(html_response(200, html))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_response(status: u16, html: String) -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(html.into_bytes())
+        .with_status_code(status)
+        .with_header(content_type("text/html; charset=utf-8"))
+}
+
+fn text_response(status: u16, text: &str) -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(format!("{}\n", text).into_bytes())
+        .with_status_code(status)
+        .with_header(content_type("text/plain; charset=utf-8"))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).unwrap()
+}
@@ -0,0 +1,176 @@
+//! Summarizes the size of a database produced by the `extract` command
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::Args;
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use crate::db;
+
+/// The number of bodies decompressed to estimate the uncompressed size
+/// (without `--uncompressed-sizes`)
+const ESTIMATE_SAMPLE_SIZE: i64 = 1000;
+
+#[derive(Debug, Args)]
+pub struct StatsCommand {
+    /// The database to summarize
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// Decompress every article body, to get the exact uncompressed sizes
+    ///
+    /// Otherwise the uncompressed size is estimated
+    /// from the compression ratio of the first 1000 bodies.
+    #[clap(long)]
+    uncompressed_sizes: bool,
+    /// The number of largest articles to list
+    #[clap(long, default_value = "10")]
+    top: u64,
+    /// Print the statistics as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct DatabaseStats {
+    articles: u64,
+    /// The number of article bodies (which may differ from the number of articles)
+    bodies: u64,
+    compressed_bytes: u64,
+    uncompressed_bytes: u64,
+    /// Whether `uncompressed_bytes` is exact, rather than estimated
+    uncompressed_exact: bool,
+    average_compressed_bytes: u64,
+    median_compressed_bytes: u64,
+    average_uncompressed_bytes: u64,
+    /// The largest articles by compressed size
+    largest: Vec<LargeArticle>,
+}
+
+#[derive(Serialize)]
+struct LargeArticle {
+    name: String,
+    compressed_bytes: u64,
+}
+
+pub fn main(command: StatsCommand) -> anyhow::Result<()> {
+    let conn = db::open_readonly(&command.database)?;
+    let articles: u64 = conn.query_row("SELECT COUNT(*) FROM article", [], |row| row.get(0))?;
+    let (bodies, compressed_bytes): (u64, u64) = conn.query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(compressed_html)), 0) FROM article_body",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let median_compressed_bytes: u64 = if bodies > 0 {
+        conn.query_row(
+            "SELECT LENGTH(compressed_html) FROM article_body \
+            ORDER BY LENGTH(compressed_html) LIMIT 1 OFFSET ?1",
+            [bodies / 2],
+            |row| row.get(0),
+        )?
+    } else {
+        0
+    };
+    // The first bodies are cheap to read by their rowid, and are a good enough sample
+    let sample_limit = if command.uncompressed_sizes {
+        -1
+    } else {
+        ESTIMATE_SAMPLE_SIZE
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT compressed_html, {} FROM article_body ORDER BY id LIMIT ?1",
+        db::compression_expr(&conn)?
+    ))?;
+    let mut rows = stmt.query([sample_limit])?;
+    let (mut sample_compressed, mut sample_uncompressed) = (0u64, 0u64);
+    while let Some(row) = rows.next()? {
+        let body: Vec<u8> = row.get(0)?;
+        let compression: String = row.get(1)?;
+        sample_compressed += body.len() as u64;
+        sample_uncompressed += db::decode_body(body, &compression)?.len() as u64;
+    }
+    let uncompressed_bytes = if command.uncompressed_sizes || sample_compressed == 0 {
+        sample_uncompressed
+    } else {
+        (compressed_bytes as f64 * sample_uncompressed as f64 / sample_compressed as f64) as u64
+    };
+    let mut stmt = conn.prepare(
+        "SELECT article.name, LENGTH(article_body.compressed_html) FROM article_body \
+        JOIN article ON article.id = article_body.article_id \
+        ORDER BY 2 DESC LIMIT ?1",
+    )?;
+    let largest = stmt
+        .query_map([command.top], |row| {
+            Ok(LargeArticle {
+                name: row.get(0)?,
+                compressed_bytes: row.get(1)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let stats = DatabaseStats {
+        articles,
+        bodies,
+        compressed_bytes,
+        uncompressed_bytes,
+        uncompressed_exact: command.uncompressed_sizes || bodies as i64 <= ESTIMATE_SAMPLE_SIZE,
+        average_compressed_bytes: compressed_bytes.checked_div(bodies).unwrap_or(0),
+        median_compressed_bytes,
+        average_uncompressed_bytes: uncompressed_bytes.checked_div(bodies).unwrap_or(0),
+        largest,
+    };
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if command.json {
+        serde_json::to_writer_pretty(&mut out, &stats)?;
+        writeln!(out)?;
+    } else {
+        write_human(&mut out, &stats)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_human(out: &mut dyn Write, stats: &DatabaseStats) -> std::io::Result<()> {
+    writeln!(out, "Articles:            {}", stats.articles)?;
+    if stats.bodies != stats.articles {
+        writeln!(out, "Bodies:              {}", stats.bodies)?;
+    }
+    writeln!(
+        out,
+        "Compressed size:     {}",
+        HumanBytes(stats.compressed_bytes)
+    )?;
+    writeln!(
+        out,
+        "Uncompressed size:   {}{}",
+        HumanBytes(stats.uncompressed_bytes),
+        if stats.uncompressed_exact {
+            ""
+        } else {
+            " (estimated, use --uncompressed-sizes for the exact size)"
+        }
+    )?;
+    writeln!(
+        out,
+        "Average size:        {} compressed, {} uncompressed",
+        HumanBytes(stats.average_compressed_bytes),
+        HumanBytes(stats.average_uncompressed_bytes)
+    )?;
+    writeln!(
+        out,
+        "Median size:         {} compressed",
+        HumanBytes(stats.median_compressed_bytes)
+    )?;
+    if !stats.largest.is_empty() {
+        writeln!(out, "Largest articles:")?;
+        for article in &stats.largest {
+            writeln!(
+                out,
+                "  {:>12}  {}",
+                HumanBytes(article.compressed_bytes).to_string(),
+                article.name
+            )?;
+        }
+    }
+    Ok(())
+}
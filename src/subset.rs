@@ -0,0 +1,80 @@
+//! Copies a subset of the articles in a database into a new database
+use std::path::PathBuf;
+
+use anyhow::bail;
+use clap::Args;
+use log::info;
+
+use crate::db;
+use crate::extract::titles::TitleSet;
+use crate::merge;
+
+#[derive(Debug, Args)]
+pub struct SubsetCommand {
+    /// The database to copy the articles from
+    #[clap(required = true, parse(from_os_str))]
+    source: PathBuf,
+    /// The file listing the titles of the articles to copy (one per line)
+    ///
+    /// Titles are matched like `extract --titles-file`,
+    /// ignoring underscores and percent escapes.
+    #[clap(long, required = true, parse(from_os_str))]
+    titles_file: PathBuf,
+    /// The new database to create
+    #[clap(long = "out", required = true, parse(from_os_str))]
+    output: PathBuf,
+}
+
+pub fn main(command: SubsetCommand) -> anyhow::Result<()> {
+    if command.output.exists() {
+        bail!(
+            "Output database already exists: {}",
+            command.output.display()
+        );
+    }
+    let titles = TitleSet::load(&command.titles_file)?;
+    info!("Loaded {} titles", titles.len());
+    let source = db::open_readonly(&command.source)?;
+    let mut ids = Vec::new();
+    {
+        let mut stmt = source.prepare("SELECT id, name FROM article")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(1)?;
+            if titles.contains(&name) {
+                ids.push(row.get::<_, i64>(0)?);
+            }
+        }
+    }
+    drop(source);
+    titles.report();
+    let mut connection = db::open_or_create(&command.output)?;
+    db::create_indexes(&connection)?;
+    /*
+     * The matching ids are collected into a temporary table,
+     * so the merge can copy just those articles (remapping their ids as usual).
+     */
+    connection.execute("CREATE TEMP TABLE subset_id(id INTEGER PRIMARY KEY)", [])?;
+    {
+        let tx = connection.transaction()?;
+        {
+            let mut stmt = tx.prepare("INSERT INTO temp.subset_id(id) VALUES(?1)")?;
+            for id in &ids {
+                stmt.execute([id])?;
+            }
+        }
+        tx.commit()?;
+    }
+    let count = merge::merge_source(
+        &mut connection,
+        &command.source,
+        Some("id IN (SELECT id FROM temp.subset_id)"),
+    )?;
+    connection.execute("DROP TABLE temp.subset_id", [])?;
+    info!(
+        "Copied {} articles into {}",
+        count.merged,
+        command.output.display()
+    );
+    Ok(())
+}
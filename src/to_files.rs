@@ -0,0 +1,168 @@
+//! Writes the articles of a database out into files, like the `extract-files` command
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use log::{debug, info, warn};
+
+use crate::cachedir::ExtractionMeta;
+use crate::db;
+use crate::extract::files::FileLayout;
+use crate::extract::url::parse_url;
+use crate::nesting::DirCache;
+
+#[derive(Debug, Args)]
+pub struct ToFilesCommand {
+    /// The database to read the articles from
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// The target directory to write the files into
+    #[clap(long = "out", required = true, parse(from_os_str))]
+    output_dir: PathBuf,
+    #[clap(flatten)]
+    layout: FileLayout,
+    /// Skip existing files
+    #[clap(long)]
+    skip_existing: bool,
+    /// The number of threads decompressing and writing files (defaults to the number of CPUs)
+    #[clap(long = "workers", short = 'j')]
+    workers: Option<u32>,
+    /// Don't write a `CACHEDIR.TAG` and `extraction-meta.json` into the output directory
+    ///
+    /// The tag tells backup tools that the contents are regenerable.
+    #[clap(long)]
+    no_cachedir_tag: bool,
+}
+
+/// An article read from the database, which still has to be decompressed
+struct StoredArticle {
+    url: String,
+    body: Vec<u8>,
+    compression: String,
+}
+
+/// The state shared by all the workers
+struct ToFilesState {
+    layout: FileLayout,
+    output_dir: PathBuf,
+    skip_existing: bool,
+    existing_dirs: DirCache,
+    written: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+}
+
+pub fn main(command: ToFilesCommand) -> anyhow::Result<()> {
+    let workers = command.workers.unwrap_or_else(crate::default_workers);
+    if workers == 0 {
+        bail!("Must have at least one worker");
+    }
+    info!("Using {} workers", workers);
+    let conn = db::open_readonly(&command.database)?;
+    std::fs::create_dir_all(&command.output_dir)?;
+    let state = Arc::new(ToFilesState {
+        layout: command.layout,
+        output_dir: command.output_dir,
+        skip_existing: command.skip_existing,
+        existing_dirs: DirCache::new(),
+        written: AtomicU64::new(0),
+        skipped: AtomicU64::new(0),
+        failed: AtomicU64::new(0),
+    });
+    let (sender, receiver) = crossbeam::channel::bounded::<StoredArticle>(500);
+    let mut handles = Vec::new();
+    for index in 0..workers {
+        let state = Arc::clone(&state);
+        let receiver = receiver.clone();
+        handles.push(
+            std::thread::Builder::new()
+                .name(format!("to-files-worker-{}", index))
+                .spawn(move || {
+                    while let Ok(article) = receiver.recv() {
+                        let url = article.url.clone();
+                        if let Err(e) = write_article(&state, article) {
+                            warn!("Failed to write {}: {}", url, e);
+                            state.failed.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                })?,
+        );
+    }
+    let res = send_articles(&conn, &sender);
+    drop(sender);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    res?;
+    let written = state.written.load(Ordering::SeqCst);
+    info!(
+        "Wrote {} files ({} skipped, {} failed)",
+        written,
+        state.skipped.load(Ordering::SeqCst),
+        state.failed.load(Ordering::SeqCst)
+    );
+    if !command.no_cachedir_tag {
+        let meta = ExtractionMeta::new(&[&command.database], written);
+        crate::cachedir::mark_output_dir(&state.output_dir, &meta)?;
+    }
+    let failed = state.failed.load(Ordering::SeqCst);
+    if failed > 0 {
+        bail!("Failed to write {} articles", failed);
+    }
+    Ok(())
+}
+
+/// Read the articles from the database, sending them to the workers
+fn send_articles(
+    conn: &rusqlite::Connection,
+    sender: &crossbeam::channel::Sender<StoredArticle>,
+) -> anyhow::Result<()> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT article.url, article_body.compressed_html, {} FROM article \
+        JOIN article_body ON article_body.article_id = article.id \
+        ORDER BY article.id",
+        db::compression_expr(conn)?
+    ))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let article = StoredArticle {
+            url: row.get(0)?,
+            body: row.get(1)?,
+            compression: row.get(2)?,
+        };
+        // The workers only stop early if they panicked
+        if sender.send(article).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn write_article(state: &ToFilesState, article: StoredArticle) -> anyhow::Result<()> {
+    let url = match parse_url(&article.url) {
+        Ok(url) => url,
+        Err(msg) => {
+            warn!("{}", msg);
+            state.skipped.fetch_add(1, Ordering::SeqCst);
+            return Ok(());
+        }
+    };
+    let (dir, name) = state.layout.locate(&state.output_dir, &url);
+    state.existing_dirs.create_dir_all(&dir)?;
+    let target_file = dir.join(name);
+    if state.skip_existing && target_file.is_file() {
+        state.skipped.fetch_add(1, Ordering::SeqCst);
+        return Ok(());
+    }
+    let html = db::decode_body(article.body, &article.compression)?;
+    std::fs::write(&target_file, html.as_bytes())
+        .map_err(|e| anyhow!("Failed to write to {}: {}", target_file.display(), e))?;
+    let i = state.written.fetch_add(1, Ordering::SeqCst) + 1;
+    if i.is_multiple_of(500) {
+        info!("Wrote {} files", i);
+    }
+    debug!("Wrote {}", target_file.display());
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! Moves the files nested by `ensure-nested` (or `extract-files`) back into a single directory
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail};
+use clap::Args;
+use log::{debug, info, warn};
+
+#[derive(Debug, Args)]
+pub struct UnnestCommand {
+    /// The nested directory to flatten
+    #[clap(required = true, parse(from_os_str))]
+    target_dir: PathBuf,
+    /// The number of directories the files are nested under
+    ///
+    /// Files nested less deeply are also moved, but deeper directories are left alone.
+    #[clap(long, default_value = "2")]
+    nest_depth: usize,
+    /// The number of threads moving files (defaults to the number of CPUs)
+    #[clap(long = "workers", short = 'j')]
+    workers: Option<u32>,
+    /// Log the planned moves without performing them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+pub fn main(cmd: UnnestCommand) -> anyhow::Result<()> {
+    let workers = cmd.workers.unwrap_or_else(crate::default_workers);
+    if workers == 0 {
+        bail!("Must have at least one worker");
+    }
+    info!("Using {} workers", workers);
+    let target_dir = cmd.target_dir;
+    let dry_run = cmd.dry_run;
+    let counter = Arc::new(AtomicU64::new(0));
+    let (sender, receiver) = crossbeam::channel::bounded::<PathBuf>(500);
+    let mut handles = Vec::new();
+    for index in 0..workers {
+        let target_dir = PathBuf::clone(&target_dir);
+        let counter = Arc::clone(&counter);
+        let receiver = receiver.clone();
+        handles.push(
+            std::thread::Builder::new()
+                .name(format!("unnest-worker-{}", index))
+                .spawn(move || {
+                    while let Ok(target) = receiver.recv() {
+                        process_file(&counter, &target_dir, dry_run, &target);
+                    }
+                })?,
+        );
+    }
+    let mut walker = Walker {
+        sender,
+        seen: HashSet::new(),
+        dirs: Vec::new(),
+    };
+    let res = walker.walk(&target_dir, 0, cmd.nest_depth);
+    let Walker { sender, dirs, .. } = walker;
+    drop(sender);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    res?;
+    if dry_run {
+        info!("Would move {} files", counter.load(Ordering::SeqCst));
+        return Ok(());
+    }
+    // Each directory is recorded after its children, so they are removed first
+    let mut removed = 0u64;
+    for dir in &dirs {
+        match std::fs::remove_dir(dir) {
+            Ok(()) => removed += 1,
+            Err(e) => debug!("Not removing {}: {}", dir.display(), e),
+        }
+    }
+    info!(
+        "Moved {} files, removing {} empty directories",
+        counter.load(Ordering::SeqCst),
+        removed
+    );
+    Ok(())
+}
+
+/// Walks the nested directories, sending their files to the workers
+struct Walker {
+    sender: crossbeam::channel::Sender<PathBuf>,
+    /// The names of the files which have already been sent
+    seen: HashSet<OsString>,
+    /// The nested directories which have been walked
+    dirs: Vec<PathBuf>,
+}
+impl Walker {
+    fn walk(&mut self, dir: &Path, depth: usize, max_depth: usize) -> anyhow::Result<()> {
+        let iterdir = std::fs::read_dir(dir)
+            .map_err(|e| anyhow!("Unable to read directory {}: {}", dir.display(), e))?;
+        for entry in iterdir {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Failed to read entry: {}", e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let ft = match entry.file_type() {
+                Ok(ft) => ft,
+                Err(e) => {
+                    warn!("Failed to fetch file type of {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if ft.is_dir() {
+                if depth < max_depth {
+                    self.walk(&path, depth + 1, max_depth)?;
+                    self.dirs.push(path);
+                }
+            } else if depth == 0 {
+                // Already at the top level
+            } else if !self.seen.insert(entry.file_name()) {
+                warn!(
+                    "Not moving {}, because another file has the same name",
+                    path.display()
+                );
+            } else {
+                self.sender.send(path).unwrap();
+            }
+        }
+        Ok(())
+    }
+}
+
+fn process_file(i: &AtomicU64, target_dir: &Path, dry_run: bool, original_path: &Path) {
+    let name = match original_path.file_name() {
+        Some(name) => name,
+        None => {
+            warn!("Path has no name: {}", original_path.display());
+            return;
+        }
+    };
+    let target_file = target_dir.join(name);
+    if target_file.exists() {
+        warn!(
+            "Not moving {}, because {} already exists",
+            original_path.display(),
+            target_file.display()
+        );
+        return;
+    }
+    if dry_run {
+        info!(
+            "Would move {} to {}",
+            original_path.display(),
+            target_file.display()
+        );
+        i.fetch_add(1, Ordering::SeqCst);
+        return;
+    }
+    if let Err(e) = std::fs::rename(original_path, &target_file) {
+        warn!("Failed to rename {}: {}", original_path.display(), e);
+        return;
+    }
+    let i = i.fetch_add(1, Ordering::SeqCst);
+    if i.is_multiple_of(100) {
+        info!("Moved {} files", i);
+    }
+    if i.is_multiple_of(500) {
+        info!(
+            "Moved {} to {}",
+            original_path.display(),
+            target_file.display()
+        );
+    }
+}
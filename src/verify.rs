@@ -0,0 +1,84 @@
+//! Checks the consistency of a database produced by the `extract` command
+use std::path::PathBuf;
+
+use anyhow::bail;
+use clap::Args;
+use log::{error, info};
+
+use crate::db;
+
+#[derive(Debug, Args)]
+pub struct VerifyCommand {
+    /// The database to verify
+    #[clap(required = true, parse(from_os_str))]
+    database: PathBuf,
+    /// The limit on the number of article bodies to decode
+    #[clap(long)]
+    limit: Option<u64>,
+}
+
+pub fn main(command: VerifyCommand) -> anyhow::Result<()> {
+    let conn = db::open_readonly(&command.database)?;
+    let mut failures = 0u64;
+
+    info!("Checking integrity");
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if problems != ["ok"] {
+        for problem in &problems {
+            error!("Integrity check failed: {}", problem);
+        }
+        failures += problems.len() as u64;
+    }
+
+    info!("Checking article references");
+    let dangling: u64 = conn.query_row(
+        "SELECT COUNT(*) FROM article_body \
+        WHERE NOT EXISTS(SELECT 1 FROM article WHERE article.id = article_body.article_id)",
+        [],
+        |row| row.get(0),
+    )?;
+    if dangling > 0 {
+        error!("{} bodies reference a missing article", dangling);
+        failures += dangling;
+    }
+    let missing: u64 = conn.query_row(
+        "SELECT COUNT(*) FROM article \
+        WHERE NOT EXISTS(SELECT 1 FROM article_body WHERE article_body.article_id = article.id)",
+        [],
+        |row| row.get(0),
+    )?;
+    if missing > 0 {
+        error!("{} articles are missing a body", missing);
+        failures += missing;
+    }
+
+    info!("Decoding article bodies");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT article_body.id, article_body.compressed_html, {} FROM article_body \
+        ORDER BY article_body.id LIMIT ?1",
+        db::compression_expr(&conn)?
+    ))?;
+    let limit = command.limit.map_or(-1, |limit| limit as i64);
+    let mut rows = stmt.query([limit])?;
+    let mut decoded = 0u64;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let body: Vec<u8> = row.get(1)?;
+        let compression: String = row.get(2)?;
+        if let Err(cause) = db::decode_body(body, &compression) {
+            error!("Unable to decode body {}: {}", id, cause);
+            failures += 1;
+        }
+        decoded += 1;
+    }
+    info!("Decoded {} article bodies", decoded);
+
+    if failures > 0 {
+        bail!("Verification failed with {} problems", failures);
+    }
+    info!("Database is consistent");
+    Ok(())
+}
@@ -0,0 +1,56 @@
+//! Runs the `ensure-nested` command on a directory of flat files
+use std::path::Path;
+
+use assert_cmd::Command;
+
+fn ensure_nested(dir: &Path, args: &[&str]) {
+    Command::cargo_bin("wikipedia-html-extractor")
+        .unwrap()
+        .arg("ensure-nested")
+        .args(args)
+        .arg(dir)
+        .assert()
+        .success();
+}
+
+fn read(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn moves_flat_files_into_nested_dirs() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("Foo.html"), "foo").unwrap();
+    std::fs::write(dir.path().join("Bar.html"), "bar").unwrap();
+    ensure_nested(dir.path(), &[]);
+    assert_eq!(read(&dir.path().join("F/o/Foo.html")), "foo");
+    assert_eq!(read(&dir.path().join("B/a/Bar.html")), "bar");
+    assert!(!dir.path().join("Foo.html").exists());
+    assert!(!dir.path().join("Bar.html").exists());
+}
+
+/// A flat `Foo.html` whose nested path is already taken by another file
+fn colliding_dir() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("F/o")).unwrap();
+    std::fs::write(dir.path().join("F/o/Foo.html"), "nested").unwrap();
+    std::fs::write(dir.path().join("Foo.html"), "flat").unwrap();
+    dir
+}
+
+#[test]
+fn collision_skips_by_default() {
+    let dir = colliding_dir();
+    ensure_nested(dir.path(), &[]);
+    assert_eq!(read(&dir.path().join("Foo.html")), "flat");
+    assert_eq!(read(&dir.path().join("F/o/Foo.html")), "nested");
+}
+
+#[test]
+fn collision_renames_with_a_suffix() {
+    let dir = colliding_dir();
+    ensure_nested(dir.path(), &["--on-collision", "rename"]);
+    assert!(!dir.path().join("Foo.html").exists());
+    assert_eq!(read(&dir.path().join("F/o/Foo.html")), "nested");
+    assert_eq!(read(&dir.path().join("F/o/Foo-1.html")), "flat");
+}
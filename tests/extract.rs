@@ -0,0 +1,428 @@
+//! Runs the `extract` command on the fixture dump, checking the resulting database
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use assert_cmd::assert::Assert;
+use assert_cmd::Command;
+use rusqlite::{Connection, OpenFlags};
+use serde_json::Value;
+use wikipedia_html_extractor::fixture::gen_fixture;
+
+/// The number of distinct articles in the fixture (which also has a duplicate)
+const FIXTURE_ARTICLES: u64 = 5;
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/articles.ndjson")
+}
+
+/// The articles of the fixture, as raw JSON
+fn fixture_articles() -> Vec<Value> {
+    std::fs::read_to_string(fixture())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Write a copy of the fixture with the specified lines inserted (in order)
+fn corrupt_fixture(dir: &Path, insertions: &[(usize, &str)]) -> PathBuf {
+    let path = dir.join("corrupt.ndjson");
+    let fixture = std::fs::read_to_string(fixture()).unwrap();
+    let mut lines = fixture.lines().collect::<Vec<_>>();
+    for &(index, line) in insertions {
+        lines.insert(index, line);
+    }
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    path
+}
+
+fn command() -> Command {
+    Command::cargo_bin("wikipedia-html-extractor").unwrap()
+}
+
+/// Extract the fixture into the database
+fn extract(db: &Path, args: &[&str]) {
+    extract_input(db, &fixture(), args).success();
+}
+
+/// Extract the specified input into the database, without checking the outcome
+fn extract_input(db: &Path, input: &Path, args: &[&str]) -> Assert {
+    command()
+        .arg("extract")
+        .arg("--out")
+        .arg(db)
+        .args(args)
+        .arg(input)
+        .assert()
+}
+
+fn stderr(assert: &Assert) -> String {
+    String::from_utf8_lossy(&assert.get_output().stderr).into_owned()
+}
+
+fn open(db: &Path) -> Connection {
+    Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap()
+}
+
+fn count(conn: &Connection, table: &str) -> u64 {
+    conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+        row.get(0)
+    })
+    .unwrap()
+}
+
+#[test]
+fn extracts_every_article_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &[]);
+    let conn = open(&db);
+    assert_eq!(count(&conn, "article"), FIXTURE_ARTICLES);
+    assert_eq!(count(&conn, "article_body"), FIXTURE_ARTICLES);
+    let names = conn
+        .prepare("SELECT name FROM article ORDER BY id")
+        .unwrap()
+        .query_map([], |row| row.get::<_, String>(0))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(names, ["Foo", "AC/DC", "Talk:Foo", "C++", "Café"]);
+}
+
+#[test]
+fn keeps_the_first_duplicate() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &[]);
+    command()
+        .args(["query", db.to_str().unwrap(), "Foo"])
+        .assert()
+        .success()
+        .stdout(
+            fixture_articles()[0]["article_body"]["html"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        );
+}
+
+#[test]
+fn extracting_again_inserts_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &[]);
+    extract(&db, &[]);
+    let conn = open(&db);
+    assert_eq!(count(&conn, "article"), FIXTURE_ARTICLES);
+    assert_eq!(count(&conn, "article_body"), FIXTURE_ARTICLES);
+}
+
+#[test]
+fn bodies_round_trip_through_zstd() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &["--compression-level", "3"]);
+    let conn = open(&db);
+    // The duplicate is skipped, so only the first articles are stored
+    for article in &fixture_articles()[..FIXTURE_ARTICLES as usize] {
+        let (compressed, compression): (Vec<u8>, String) = conn
+            .query_row(
+                "SELECT article_body.compressed_html, article_body.compression FROM article \
+                JOIN article_body ON article_body.article_id = article.id \
+                WHERE article.name = ?1",
+                [article["name"].as_str().unwrap()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(compression, "zstd");
+        let html = String::from_utf8(zstd::decode_all(&*compressed).unwrap()).unwrap();
+        assert_eq!(html, article["article_body"]["html"].as_str().unwrap());
+    }
+}
+
+#[test]
+fn stores_urls_and_languages() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &[]);
+    let conn = open(&db);
+    let (url, lang, page_id): (String, String, u64) = conn
+        .query_row(
+            "SELECT url, lang, page_id FROM article WHERE name = 'Café'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(url, "https://fr.wikipedia.org/wiki/Caf%C3%A9");
+    assert_eq!(lang, "fr");
+    assert_eq!(page_id, 104);
+    command()
+        .args(["query", db.to_str().unwrap(), "--url", &url])
+        .assert()
+        .success()
+        .stdout(
+            fixture_articles()[4]["article_body"]["html"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        );
+}
+
+#[test]
+fn skips_invalid_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = corrupt_fixture(
+        dir.path(),
+        &[
+            (2, r#"{"name": not json"#),
+            (4, r#"{"name": "Truncated", "url": "#),
+        ],
+    );
+    let db = dir.path().join("articles.db");
+    // Parse errors fail the extraction, unless they are ignored
+    extract_input(&db, &input, &[]).failure();
+    std::fs::remove_file(&db).unwrap();
+    extract_input(&db, &input, &["--ignore-errors"]).success();
+    let conn = open(&db);
+    // Reading restarts at the line following a truncated record
+    assert_eq!(count(&conn, "article"), FIXTURE_ARTICLES);
+}
+
+#[test]
+fn resume_skips_only_clean_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let corrupt = corrupt_fixture(dir.path(), &[(2, "not json")]);
+    let db = dir.path().join("articles.db");
+    // Files are recorded even without `--resume`, so any extraction can be resumed
+    extract_input(&db, &fixture(), &[]).success();
+    extract_input(&db, &corrupt, &["--ignore-errors"]).success();
+    let conn = open(&db);
+    let recorded = conn
+        .prepare("SELECT path FROM processed_file")
+        .unwrap()
+        .query_map([], |row| row.get::<_, String>(0))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert!(recorded[0].ends_with("articles.ndjson"), "{:?}", recorded);
+    // The clean file is skipped, but the corrupt one is extracted again
+    let skipped = extract_input(&db, &fixture(), &["--resume"]).success();
+    assert!(stderr(&skipped).contains("Skipping already processed"));
+    let extracted = extract_input(&db, &corrupt, &["--resume", "--ignore-errors"]).success();
+    assert!(stderr(&extracted).contains("Processing"));
+}
+
+#[test]
+fn limit_stores_exactly_that_many_articles() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("synthetic.ndjson");
+    std::fs::write(&input, gen_fixture(200, 30)).unwrap();
+    let db = dir.path().join("articles.db");
+    // Several workers race to parse articles past the limit
+    extract_input(&db, &input, &["--limit", "17", "-j", "4"]).success();
+    assert_eq!(count(&open(&db), "article"), 17);
+}
+
+#[test]
+fn limit_counts_only_new_articles_across_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    // Mostly duplicates, whose reserved slots are given back
+    let input = dir.path().join("repeated.ndjson");
+    let fixture = std::fs::read_to_string(fixture()).unwrap();
+    std::fs::write(&input, fixture.repeat(50)).unwrap();
+    let db = dir.path().join("articles.db");
+    let limit = FIXTURE_ARTICLES.to_string();
+    extract_input(
+        &db,
+        &input,
+        &["--limit", &limit, "-j", "4", "--shards", "2"],
+    )
+    .success();
+    let inserted: u64 = ["articles-000.db", "articles-001.db"]
+        .iter()
+        .map(|shard| count(&open(&dir.path().join(shard)), "article"))
+        .sum();
+    assert_eq!(inserted, FIXTURE_ARTICLES);
+}
+
+#[test]
+fn word_count_estimates_reading_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &["--word-count"]);
+    let conn = open(&db);
+    let query = "SELECT word_count, reading_seconds FROM article WHERE name = ?1";
+    let row = |name: &str| {
+        conn.query_row(query, [name], |row| {
+            Ok((row.get::<_, u64>(0)?, row.get::<_, u64>(1)?))
+        })
+        .unwrap()
+    };
+    // "Foo is a bar." takes 1.2 seconds at 200 words per minute, rounded up
+    assert_eq!(row("Foo"), (4, 2));
+    assert_eq!(row("Talk:Foo"), (3, 1));
+}
+
+#[test]
+fn word_count_is_null_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &[]);
+    let conn = open(&db);
+    assert_eq!(
+        count(
+            &conn,
+            "article WHERE word_count IS NULL AND reading_seconds IS NULL"
+        ),
+        FIXTURE_ARTICLES
+    );
+}
+
+/// The names of the extracted articles, in order
+fn names(conn: &Connection) -> Vec<String> {
+    conn.prepare("SELECT name FROM article ORDER BY id")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[test]
+fn json_array_extracts_like_ndjson() {
+    let dir = tempfile::tempdir().unwrap();
+    let array = dir.path().join("articles.json");
+    let pretty = serde_json::to_string_pretty(&fixture_articles()).unwrap();
+    std::fs::write(&array, pretty).unwrap();
+    let from_array = dir.path().join("array.db");
+    extract_input(&from_array, &array, &[]).success();
+    let from_ndjson = dir.path().join("ndjson.db");
+    extract(&from_ndjson, &[]);
+    let from_array = names(&open(&from_array));
+    assert_eq!(from_array.len() as u64, FIXTURE_ARTICLES);
+    assert_eq!(from_array, names(&open(&from_ndjson)));
+}
+
+#[test]
+fn skips_a_byte_order_mark_and_crlf() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("windows.ndjson");
+    let fixture = std::fs::read_to_string(fixture()).unwrap();
+    let lines = fixture.lines().collect::<Vec<_>>();
+    std::fs::write(&input, format!("\u{FEFF}{}\r\n", lines.join("\r\n"))).unwrap();
+    let db = dir.path().join("articles.db");
+    extract_input(&db, &input, &[]).success();
+    assert_eq!(count(&open(&db), "article"), FIXTURE_ARTICLES);
+}
+
+#[test]
+fn update_restores_missing_bodies() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    extract(&db, &[]);
+    // Like an article merged from a partial database
+    Connection::open(&db)
+        .unwrap()
+        .execute(
+            "DELETE FROM article_body WHERE article_id = \
+            (SELECT id FROM article WHERE name = 'Foo')",
+            [],
+        )
+        .unwrap();
+    extract(&db, &["--update"]);
+    let conn = open(&db);
+    assert_eq!(count(&conn, "article"), FIXTURE_ARTICLES);
+    assert_eq!(count(&conn, "article_body"), FIXTURE_ARTICLES);
+}
+
+/// Start extracting synthetic articles from stdin into a database which already has the fixture,
+/// returning once the first article has been written.
+///
+/// The remaining articles are only sent once the database has been locked.
+fn start_stdin_extract(db: &Path, busy_retries: u32) -> (Child, Vec<String>) {
+    extract(db, &[]);
+    let mut articles = gen_fixture(10, 89)
+        .lines()
+        .map(|line| format!("{}\n", line))
+        .collect::<Vec<_>>();
+    let mut child =
+        std::process::Command::new(assert_cmd::cargo::cargo_bin("wikipedia-html-extractor"))
+            .arg("extract")
+            .arg("--out")
+            .arg(db)
+            .args(["-q", "--no-progress", "--batch-size", "1"])
+            .args([
+                "--busy-timeout",
+                "0",
+                "--busy-retries",
+                &busy_retries.to_string(),
+            ])
+            .arg("-")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+    let first = articles.remove(0);
+    child
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(first.as_bytes())
+        .unwrap();
+    let start = Instant::now();
+    while count(&open(db), "article") == FIXTURE_ARTICLES {
+        assert!(start.elapsed() < Duration::from_secs(30), "Never started");
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    (child, articles)
+}
+
+/// Send the remaining articles while holding the write lock of the database for a while
+fn send_while_locked(
+    db: &Path,
+    duration: Duration,
+    mut child: Child,
+    articles: Vec<String>,
+) -> Output {
+    let conn = Connection::open(db).unwrap();
+    conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for article in articles {
+        stdin.write_all(article.as_bytes()).unwrap();
+    }
+    drop(stdin);
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(duration);
+        conn.execute_batch("COMMIT").unwrap();
+    });
+    let output = child.wait_with_output().unwrap();
+    release.join().unwrap();
+    output
+}
+
+#[test]
+fn retries_while_the_database_is_busy() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    let (child, articles) = start_stdin_extract(&db, 10);
+    let output = send_while_locked(&db, Duration::from_millis(300), child, articles);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("Database is busy, retrying"), "{}", stderr);
+    assert_eq!(count(&open(&db), "article"), FIXTURE_ARTICLES + 10);
+}
+
+#[test]
+fn fails_once_the_busy_retries_run_out() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("articles.db");
+    let (child, articles) = start_stdin_extract(&db, 1);
+    let output = send_while_locked(&db, Duration::from_secs(2), child, articles);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}", stderr);
+    assert!(stderr.contains("database is locked"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
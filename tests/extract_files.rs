@@ -0,0 +1,38 @@
+//! Runs the `extract-files` command, checking the files written to the output directory
+use std::path::{Path, PathBuf};
+
+use assert_cmd::Command;
+use walkdir::WalkDir;
+use wikipedia_html_extractor::fixture::gen_fixture;
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/articles.ndjson")
+}
+
+/// Count the files written anywhere below the directory
+fn count_files(dir: &Path) -> usize {
+    WalkDir::new(dir)
+        .into_iter()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().is_file())
+        .count()
+}
+
+#[test]
+fn limit_applies_across_input_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let synthetic = dir.path().join("synthetic.ndjson");
+    std::fs::write(&synthetic, gen_fixture(50, 33)).unwrap();
+    let out = dir.path().join("out");
+    Command::cargo_bin("wikipedia-html-extractor")
+        .unwrap()
+        .arg("extract-files")
+        .arg("--out")
+        .arg(&out)
+        .args(["--limit", "3", "--no-cachedir-tag"])
+        .arg(fixture())
+        .arg(&synthetic)
+        .assert()
+        .success();
+    assert_eq!(count_files(&out), 3);
+}
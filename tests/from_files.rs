@@ -0,0 +1,19 @@
+//! Runs the `from-files` command on a directory of extracted files
+use assert_cmd::Command;
+
+#[test]
+fn rejects_invalid_compression_levels() {
+    let dir = tempfile::tempdir().unwrap();
+    for level in ["0", "23"] {
+        Command::cargo_bin("wikipedia-html-extractor")
+            .unwrap()
+            .arg("from-files")
+            .arg("--out")
+            .arg(dir.path().join("articles.db"))
+            .args(["--compression-level", level])
+            .arg(dir.path())
+            .assert()
+            .failure();
+    }
+    assert!(!dir.path().join("articles.db").exists());
+}
@@ -0,0 +1,45 @@
+//! Indexes the fixture dump, then reads articles back at their offsets
+use std::path::{Path, PathBuf};
+
+use assert_cmd::Command;
+use wikipedia_html_extractor::get_at::read_at;
+use wikipedia_html_extractor::index::OffsetIndexReader;
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/articles.ndjson")
+}
+
+/// Index the fixture into the directory, returning the path of the json index
+fn index(dir: &Path) -> PathBuf {
+    Command::cargo_bin("wikipedia-html-extractor")
+        .unwrap()
+        .arg("index")
+        .arg("--out")
+        .arg(dir)
+        .args(["--format", "json", "--no-cachedir-tag"])
+        .arg(fixture())
+        .assert()
+        .success();
+    dir.join("articles-index.json")
+}
+
+#[test]
+fn reader_finds_indexed_articles() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut reader = OffsetIndexReader::open(&index(dir.path()), &fixture()).unwrap();
+    let article = reader.get("C++").unwrap().unwrap();
+    assert_eq!(article.name, "C++");
+    assert!(article.body.html.contains("C++"));
+    assert!(reader.get("Missing").unwrap().is_none());
+}
+
+#[test]
+fn out_of_range_reads_fail() {
+    let len = std::fs::metadata(fixture()).unwrap().len();
+    let error = read_at(&fixture(), len - 2, 10).unwrap_err();
+    assert!(
+        error.to_string().contains("Failed to read 10 bytes"),
+        "{}",
+        error
+    );
+}
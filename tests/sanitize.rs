@@ -0,0 +1,113 @@
+//! Checks that the reversible `--sanitize` schemes round-trip arbitrary names
+use proptest::prelude::*;
+use wikipedia_html_extractor::extract::files::{desanitize_name, sanitize_name, Sanitize};
+
+/// The schemes which are meant to be reversible
+const REVERSIBLE: &[Sanitize] = &[Sanitize::Percent, Sanitize::Windows];
+
+/// Characters which can't appear in a file name on the target OS
+#[cfg(windows)]
+fn is_illegal(c: char) -> bool {
+    c.is_ascii_control() || "/\\:*?\"<>|".contains(c)
+}
+#[cfg(not(windows))]
+fn is_illegal(c: char) -> bool {
+    c == '/' || c == '\0'
+}
+
+/// Names built from the escape sequences used by the schemes (and the legacy scheme),
+/// along with the characters they stand for.
+fn tricky_name() -> impl Strategy<Value = String> {
+    let pieces = prop::sample::select(vec![
+        "%",
+        "%2F",
+        "%25",
+        "%3A",
+        "%zz",
+        "__",
+        "__colon__",
+        "__star__",
+        "/",
+        ":",
+        "*",
+        "\\",
+        ".",
+        "CON",
+        "nul",
+        "COM1",
+        "lpt9",
+        "\0",
+        "\u{7f}",
+        "é",
+        "e\u{301}",
+        "日本",
+    ]);
+    prop::collection::vec(pieces, 0..8).prop_map(|pieces| pieces.concat())
+}
+
+fn any_name() -> impl Strategy<Value = String> {
+    prop_oneof![any::<String>(), tricky_name()]
+}
+
+proptest! {
+    #[test]
+    fn round_trips(name in any_name()) {
+        for &scheme in REVERSIBLE {
+            let sanitized = sanitize_name(&name, scheme);
+            prop_assert_eq!(desanitize_name(&sanitized, scheme).unwrap(), name.as_str());
+        }
+    }
+
+    #[test]
+    fn never_illegal(name in any_name()) {
+        for &scheme in REVERSIBLE {
+            let sanitized = sanitize_name(&name, scheme);
+            prop_assert!(
+                !sanitized.chars().any(is_illegal),
+                "{:?} sanitized to {:?} with {:?}", name, sanitized, scheme
+            );
+        }
+    }
+
+    #[test]
+    fn never_reserved_on_windows(name in any_name()) {
+        let sanitized = sanitize_name(&name, Sanitize::Windows);
+        let stem = sanitized.split('.').next().unwrap();
+        prop_assert!(
+            !["CON", "PRN", "AUX", "NUL"].iter().any(|reserved| stem.eq_ignore_ascii_case(reserved)),
+            "{:?} sanitized to the reserved {:?}", name, sanitized
+        );
+        let numbered = stem.len() == 4
+            && (stem[..3].eq_ignore_ascii_case("COM") || stem[..3].eq_ignore_ascii_case("LPT"))
+            && stem.as_bytes()[3].is_ascii_digit()
+            && stem.as_bytes()[3] != b'0';
+        prop_assert!(!numbered, "{:?} sanitized to the reserved {:?}", name, sanitized);
+    }
+
+    #[test]
+    fn distinct_names_stay_distinct(a in any_name(), b in any_name()) {
+        prop_assume!(a != b);
+        for &scheme in REVERSIBLE {
+            prop_assert_ne!(sanitize_name(&a, scheme), sanitize_name(&b, scheme));
+        }
+    }
+}
+
+/// The legacy scheme can't tell these apart, which is why it isn't the only option
+#[test]
+fn escapes_do_not_collide() {
+    for &scheme in REVERSIBLE {
+        assert_ne!(
+            sanitize_name("Foo:Bar", scheme),
+            sanitize_name("Foo__colon__Bar", scheme)
+        );
+        assert_ne!(
+            sanitize_name("AC/DC", scheme),
+            sanitize_name("AC__DC", scheme)
+        );
+        assert_ne!(
+            sanitize_name("100%", scheme),
+            sanitize_name("100%25", scheme)
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Parsing the urls of articles into their title and language
+use wikipedia_html_extractor::extract::url::{parse_url, ArticleUrl};
+
+fn article(title: &str, lang: Option<&str>) -> ArticleUrl {
+    ArticleUrl {
+        title: title.to_string(),
+        lang: lang.map(str::to_string),
+    }
+}
+
+#[test]
+fn relative_urls_have_no_language() {
+    assert_eq!(parse_url("/wiki/Foo").unwrap(), article("Foo", None));
+    assert_eq!(parse_url("./wiki/AC/DC").unwrap(), article("AC/DC", None));
+}
+
+#[test]
+fn absolute_urls_have_the_wiki_language() {
+    assert_eq!(
+        parse_url("https://de.wikipedia.org/wiki/M%C3%BCnchen").unwrap(),
+        article("München", Some("de"))
+    );
+    assert_eq!(
+        parse_url("https://en.m.wikipedia.org/wiki/C%2B%2B").unwrap(),
+        article("C++", Some("en"))
+    );
+}
+
+#[test]
+fn other_sites_have_no_language() {
+    assert_eq!(
+        parse_url("https://en.wiktionary.org/wiki/foo").unwrap(),
+        article("foo", None)
+    );
+    assert_eq!(
+        parse_url("https://www.wikipedia.org/wiki/Foo").unwrap(),
+        article("Foo", None)
+    );
+}
+
+#[test]
+fn urls_without_a_wiki_path_are_rejected() {
+    assert!(parse_url("https://example.com/Foo").is_err());
+}
+
+#[test]
+fn invalid_percent_sequences_are_kept() {
+    assert_eq!(
+        parse_url("/wiki/100%_Pure").unwrap(),
+        article("100%_Pure", None)
+    );
+}